@@ -8,6 +8,7 @@ use std::path::PathBuf;
 
 fn main() {
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rustc-env=TANTIVY_REV={}", tantivy_rev(&crate_dir));
 
     let package_name = env::var("CARGO_PKG_NAME").unwrap();
     let output_file = target_dir()
@@ -43,7 +44,12 @@ fn main() {
             include: vec![],
             exclude: vec![],
             prefix: Some("".to_owned()),
-            item_types: vec![ItemType::Enums, ItemType::Structs, ItemType::Functions],
+            item_types: vec![
+                ItemType::Enums,
+                ItemType::Structs,
+                ItemType::Typedefs,
+                ItemType::Functions,
+            ],
             body: std::collections::HashMap::<String, String>::new(),
             mangle: MangleConfig {
                 rename_types: RenameRule::default(),
@@ -68,6 +74,18 @@ fn main() {
     }
 }
 
+/// Find the tantivy revision pinned in Cargo.toml, reported by the system version method.
+fn tantivy_rev(crate_dir: &str) -> String {
+    let manifest = std::fs::read_to_string(PathBuf::from(crate_dir).join("Cargo.toml")).unwrap();
+    manifest
+        .lines()
+        .find(|l| l.starts_with("tantivy ") || l.starts_with("tantivy="))
+        .and_then(|l| l.split("rev = \"").nth(1))
+        .and_then(|r| r.split('"').next())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
 /// Find the location of the `target/` directory. Note that this may be
 /// overridden by `cmake`, so we also need to check the `CARGO_TARGET_DIR`
 /// variable.
@@ -3,12 +3,42 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * LogCallback receives every log record that passes the level filter.  `level` runs from 1
+ * (error) to 5 (trace), `target` and `message` are UTF-8 and only valid for the duration of the
+ * call.
+ */
+typedef void (*LogCallback)(int32_t level,
+                            const uint8_t *target,
+                            uintptr_t target_len,
+                            const uint8_t *message,
+                            uintptr_t message_len);
+
 /**
  * # Safety
  *
  */
 uint8_t init(void);
 
+/**
+ * # Safety
+ *
+ * set_log_callback sends log records to cb instead of stderr, keeping those at or above level,
+ * from 0 (off) to 5 (trace).  A null cb sends logs back to stderr.  cb may be called from any
+ * thread.  Returns -1 for an unknown level.
+ */
+int8_t set_log_callback(LogCallback cb,
+                        int32_t level);
+
+/**
+ * # Safety
+ *
+ * set_log_level changes the level log records are kept at, from 0 (off) to 5 (trace).  Call it
+ * after init or set_log_callback, both set the level when they install the logger.  Logs written to
+ * stderr are still limited by ELV_RUST_LOG.  Returns -1 for an unknown level.
+ */
+int8_t set_log_level(int32_t level);
+
 /**
  * # Safety
  *
@@ -30,15 +60,58 @@ int8_t set_k_and_b(float k,
 /**
  * # Safety
  *
+ * free_data releases the response held under handle, including the negative handle of an error
+ * response from tantivy_jpc.  Returns -1 when the handle is unknown.
  */
 int free_data(int64_t handle);
 
+/**
+ * # Safety
+ *
+ * read_data copies the response held under handle into `out`, a buffer of `out_cap` bytes, and
+ * releases it.  `out_len` receives the size of the response.  Returns 0 once copied, or -1 when
+ * the handle is unknown or the buffer is still too small, in which case the response is kept.
+ */
+int read_data(int64_t handle,
+              uint8_t *out,
+              uintptr_t out_cap,
+              uintptr_t *out_len);
+
 /**
  * tantivy_jpc is the main entry point into a translation layer from Rust to Go for Tantivy this function will # Steps   * parse the input for the appropriately formatted json   * Modify internal state to reflect json requests
  * # Safety
  *
+ * The response is held under the returned handle until free_data releases it.  An error response
+ * is held the same way and returned under its negated handle, so any result below -1 must be
+ * freed too, while -1 itself means nothing is held.
  */
 int64_t tantivy_jpc(const uint8_t *msg,
                     uintptr_t len,
                     const uint8_t **ret,
                     uintptr_t *ret_len);
+
+/**
+ * # Safety
+ *
+ * tantivy_jpc_metrics returns the per call metrics in the Prometheus text exposition format.
+ * The returned handle must be released with free_data like a tantivy_jpc response.
+ */
+int64_t tantivy_jpc_metrics(const uint8_t **ret,
+                            uintptr_t *ret_len);
+
+/**
+ * # Safety
+ *
+ * tantivy_jpc_buf is tantivy_jpc writing the response into `out`, a caller owned buffer of
+ * `out_cap` bytes, so nothing is left to free.  `out_len` receives the size of the response and
+ * `status` the status of the call, 0 or -1 for an error response.  When it fits the return is
+ * that status.  When it does not fit the request has still run and its response is held under
+ * the returned positive handle, to be collected with read_data into a buffer of at least
+ * `out_len` bytes or dropped with free_data, while `status` still tells whether the call failed.
+ */
+int64_t tantivy_jpc_buf(const uint8_t *msg,
+                        uintptr_t len,
+                        uint8_t *out,
+                        uintptr_t out_cap,
+                        uintptr_t *out_len,
+                        int64_t *status);
@@ -78,14 +78,13 @@ func (jpc *JPCId) callTantivy(object, method string, params msi) (string, error)
 	cJPCParams := (*C.uchar)(unsafe.Pointer(pcJPCParams))
 	pDestinationLen := (*C.ulong)(unsafe.Pointer(&blen))
 	ttret := C.tantivy_jpc(cJPCParams, C.ulong(uint64(len(sb))), &pCDesctination, pDestinationLen)
+	if ttret == -1 {
+		return "", errors.E("Tantivy JPC Failed", errors.K.Invalid)
+	}
+	defer C.free_data(ttret)
 	if ttret < 0 {
 		return "", errors.E("Tantivy JPC Failed", errors.K.Invalid, "desc", string(C.GoBytes(unsafe.Pointer(pCDesctination), C.int(*pDestinationLen))))
 	}
-	defer func() {
-		if ttret >= 0 {
-			C.free_data(ttret)
-		}
-	}()
 	returnData := string(C.GoBytes(unsafe.Pointer(pCDesctination), C.int(*pDestinationLen)))
 	return returnData, nil
 }
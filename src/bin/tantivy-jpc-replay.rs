@@ -0,0 +1,38 @@
+//! tantivy-jpc-replay feeds a request journal back through the dispatcher.
+//!
+//! Every index directory named in the journal is recreated under the given directory, which
+//! must not hold anything the replay could clobber.  The exit code is non zero when any request
+//! was answered differently than when it was recorded.
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("usage: tantivy-jpc-replay <journal> <directory>");
+        return ExitCode::from(2);
+    }
+    unsafe {
+        tantivy_jpc::init();
+    }
+    let journal = PathBuf::from(&args[0]);
+    let directory = PathBuf::from(&args[1]);
+    if let Err(e) = std::fs::create_dir_all(&directory) {
+        eprintln!("tantivy-jpc-replay: {} {e}", directory.display());
+        return ExitCode::FAILURE;
+    }
+    match tantivy_jpc::journal::replay(&journal, &directory) {
+        Ok(report) => {
+            println!("{}", serde_json::to_string(&report).unwrap_or_default());
+            if report.mismatches.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("tantivy-jpc-replay: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! tantivy-jpc-server exposes the tantivy_jpc request envelopes without linking the static library.
+//!
+//! Requests are read one per line and answered one per line, on stdin/stdout by default or on each
+//! connection to a Unix domain socket when started with `--socket <path>`.
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+fn usage() -> ExitCode {
+    eprintln!("usage: tantivy-jpc-server [--socket <path>]");
+    ExitCode::from(2)
+}
+
+fn main() -> ExitCode {
+    let mut socket: Option<PathBuf> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => match args.next() {
+                Some(p) => socket = Some(PathBuf::from(p)),
+                None => return usage(),
+            },
+            "-h" | "--help" => {
+                usage();
+                return ExitCode::SUCCESS;
+            }
+            _ => return usage(),
+        }
+    }
+    unsafe {
+        tantivy_jpc::init();
+    }
+    let res = match socket {
+        Some(path) => tantivy_jpc::server::serve_unix(&path),
+        None => {
+            let stdin = std::io::stdin();
+            tantivy_jpc::server::serve(stdin.lock(), std::io::stdout().lock())
+        }
+    };
+    match res {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("tantivy-jpc-server: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
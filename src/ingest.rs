@@ -0,0 +1,151 @@
+use crate::make_internal_json_error;
+use crate::tsession_document::json_to_doc;
+use crate::ErrorKinds;
+use crate::InternalCallResult;
+use crate::{debug, info};
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+use serde_derive::Serialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor};
+use tantivy::schema::Schema;
+use tantivy::IndexWriter;
+
+/// Default number of line errors reported by ingest_ndjson, later errors are only counted
+pub const DEFAULT_MAX_ERRORS: usize = 1000;
+
+/// LineError is a line of an NDJSON payload that could not be added
+#[derive(Serialize, Clone, Debug)]
+pub struct LineError {
+    pub line: usize,
+    pub error: String,
+}
+
+/// IngestReport summarizes an ingest_ndjson call
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct IngestReport {
+    pub lines: usize,
+    pub added: usize,
+    pub failed: usize,
+    pub commits: usize,
+    pub opstamp: Option<u64>,
+    pub errors: Vec<LineError>,
+}
+
+impl IngestReport {
+    fn fail(&mut self, line: usize, error: String, max_errors: usize) {
+        self.failed += 1;
+        if self.errors.len() < max_errors {
+            self.errors.push(LineError { line, error });
+        }
+    }
+}
+
+// ndjson_source reads the payload from data or from the file named by path, exactly one of which
+// must be given
+fn ndjson_source(params: &serde_json::Value) -> InternalCallResult<Box<dyn BufRead>> {
+    match (params.get("data"), params.get("path")) {
+        (Some(d), None) => match d.as_str() {
+            Some(s) => Ok(Box::new(Cursor::new(s.as_bytes().to_vec()))),
+            None => make_internal_json_error(ErrorKinds::BadParams(
+                "data must be a string of newline delimited json".to_string(),
+            )),
+        },
+        (None, Some(p)) => match p.as_str() {
+            Some(path) => {
+                let f = File::open(path)
+                    .map_err(|e| ErrorKinds::IO(format!("failed to open {path} {e}")))?;
+                Ok(Box::new(BufReader::new(f)))
+            }
+            None => {
+                make_internal_json_error(ErrorKinds::BadParams("path must be a string".to_string()))
+            }
+        },
+        _ => make_internal_json_error(ErrorKinds::BadParams(
+            "exactly one of data or path is required".to_string(),
+        )),
+    }
+}
+
+fn optional_usize(params: &serde_json::Value, key: &str) -> InternalCallResult<Option<usize>> {
+    match params.get(key) {
+        Some(v) => match v.as_u64() {
+            Some(n) => Ok(Some(n as usize)),
+            None => make_internal_json_error(ErrorKinds::BadParams(format!(
+                "{key} must be an unsigned integer"
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+/// ingest_ndjson adds every line of an NDJSON payload to writer as a document of schema.  Lines
+/// that do not parse or convert are reported by their 1-based line number and skipped, blank
+/// lines are ignored.  With `commit_every` the writer is committed after that many documents and
+/// once more at the end if documents are left uncommitted.
+pub fn ingest_ndjson(
+    writer: &mut IndexWriter,
+    schema: &Schema,
+    params: &serde_json::Value,
+) -> InternalCallResult<IngestReport> {
+    let reader = ndjson_source(params)?;
+    let commit_every = optional_usize(params, "commit_every")?.filter(|n| *n > 0);
+    let max_errors = optional_usize(params, "max_errors")?.unwrap_or(DEFAULT_MAX_ERRORS);
+    let mut report = IngestReport::default();
+    let mut uncommitted = 0;
+    for (n, line) in reader.lines().enumerate() {
+        let line_no = n + 1;
+        let line = match line {
+            Ok(l) => l,
+            // the invalid line has been consumed, the rest of the payload can still be read
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                report.lines += 1;
+                report.fail(line_no, e.to_string(), max_errors);
+                continue;
+            }
+            Err(e) => return make_internal_json_error(ErrorKinds::IO(e.to_string())),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        report.lines += 1;
+        let obj = match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(serde_json::Value::Object(o)) => o,
+            Ok(_) => {
+                report.fail(line_no, "line is not a json object".to_string(), max_errors);
+                continue;
+            }
+            Err(e) => {
+                report.fail(line_no, e.to_string(), max_errors);
+                continue;
+            }
+        };
+        let doc = match json_to_doc(schema, obj) {
+            Ok(d) => d,
+            Err(e) => {
+                report.fail(line_no, e.to_string(), max_errors);
+                continue;
+            }
+        };
+        writer.add_document(doc)?;
+        report.added += 1;
+        uncommitted += 1;
+        if commit_every.is_some_and(|every| uncommitted >= every) {
+            report.opstamp = Some(writer.commit()?);
+            report.commits += 1;
+            uncommitted = 0;
+            debug!("ingest committed at line {line_no}");
+        }
+    }
+    if commit_every.is_some() && uncommitted > 0 {
+        report.opstamp = Some(writer.commit()?);
+        report.commits += 1;
+    }
+    info!(
+        "ingested {} of {} lines, {} failed",
+        report.added, report.lines, report.failed
+    );
+    Ok(report)
+}
@@ -0,0 +1,244 @@
+use crate::dispatch_request;
+use crate::lock_recover;
+use crate::make_internal_json_error;
+use crate::ErrorKinds;
+use crate::InternalCallResult;
+use crate::{debug, error, info};
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable naming the journal file, journaling starts at init when it is set
+pub const JOURNAL_ENV: &str = "ELV_JPC_JOURNAL";
+
+struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+lazy_static! {
+    static ref JOURNAL: Mutex<Option<Journal>> = Mutex::new(None);
+}
+
+// checked before taking JOURNAL so requests pay nothing while journaling is off
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// JournalEntry is a single line of the journal, the request envelope as received along with
+/// the status code it was answered with
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JournalEntry {
+    pub ts: u64,
+    pub code: i64,
+    pub request: serde_json::Value,
+}
+
+/// start opens path for appending and records every following request into it, replacing any
+/// journal already being written
+pub fn start(path: &Path) -> InternalCallResult<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| ErrorKinds::IO(format!("failed to open journal {} {e}", path.display())))?;
+    info!("journaling requests to {}", path.display());
+    *lock_recover(&JOURNAL, "JOURNAL") = Some(Journal {
+        path: path.to_path_buf(),
+        file,
+    });
+    ENABLED.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// stop closes the journal, returning the path it was written to
+pub fn stop() -> Option<PathBuf> {
+    ENABLED.store(false, Ordering::Release);
+    lock_recover(&JOURNAL, "JOURNAL").take().map(|j| j.path)
+}
+
+/// record appends input and its status code to the journal when journaling is enabled
+pub fn record(input: &str, code: i64) {
+    if !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    let entry = JournalEntry {
+        ts: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default(),
+        code,
+        request: serde_json::from_str(input)
+            .unwrap_or_else(|_| serde_json::Value::String(input.to_string())),
+    };
+    let line = match serde_json::to_string(&entry) {
+        Ok(l) => l + "\n",
+        Err(e) => {
+            error!("journal entry failed to serialize {e}");
+            return;
+        }
+    };
+    let mut journal = lock_recover(&JOURNAL, "JOURNAL");
+    if let Some(j) = journal.as_mut() {
+        // one write per line keeps entries whole when requests run concurrently
+        if let Err(e) = j.file.write_all(line.as_bytes()) {
+            error!("journal write to {} failed {e}", j.path.display());
+        }
+    }
+}
+
+/// ReplayMismatch reports a journal entry whose replay was answered with a different code
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReplayMismatch {
+    pub line: usize,
+    pub expected: i64,
+    pub got: i64,
+    pub response: String,
+}
+
+/// ReplayReport summarizes a replay
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    pub skipped: usize,
+    pub mismatches: Vec<ReplayMismatch>,
+}
+
+// Replayer rewrites journaled requests so they run against fresh sessions and directories
+struct Replayer {
+    directory: PathBuf,
+    sessions: HashMap<String, String>,
+    directories: HashMap<String, String>,
+}
+
+impl Replayer {
+    fn session(&mut self, id: &str) -> String {
+        let n = self.sessions.len();
+        self.sessions
+            .entry(id.to_string())
+            .or_insert_with(|| format!("replay-{}-{n}", std::process::id()))
+            .clone()
+    }
+
+    fn directory(&mut self, dir: &str) -> InternalCallResult<String> {
+        if let Some(d) = self.directories.get(dir) {
+            return Ok(d.clone());
+        }
+        let fresh = self
+            .directory
+            .join(format!("index-{}", self.directories.len()));
+        std::fs::create_dir_all(&fresh)
+            .map_err(|e| ErrorKinds::IO(format!("failed to create {} {e}", fresh.display())))?;
+        let fresh = fresh.display().to_string();
+        self.directories.insert(dir.to_string(), fresh.clone());
+        Ok(fresh)
+    }
+
+    fn rewrite(&mut self, v: &mut serde_json::Value) -> InternalCallResult<()> {
+        if let Some(items) = v.as_array_mut() {
+            for item in items {
+                self.rewrite(item)?;
+            }
+            return Ok(());
+        }
+        let key = if v.get("jsonrpc").is_some() {
+            "session"
+        } else {
+            "id"
+        };
+        if let Some(id) = v.get(key).and_then(|x| x.as_str()) {
+            v[key] = json!(self.session(id));
+        }
+        if let Some(id) = v
+            .get("params")
+            .and_then(|p| p.get("session"))
+            .and_then(|s| s.as_str())
+        {
+            v["params"]["session"] = json!(self.session(id));
+        }
+        if let Some(dir) = v
+            .get("params")
+            .and_then(|p| p.get("directory"))
+            .and_then(|d| d.as_str())
+        {
+            if !dir.is_empty() {
+                v["params"]["directory"] = json!(self.directory(dir)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+// is_system_request reports whether v targets the system obj, which is not replayed so a replay
+// cannot toggle journaling
+fn is_system_request(v: &serde_json::Value) -> bool {
+    v.get("obj").and_then(|o| o.as_str()) == Some("system")
+        || v.get("method")
+            .and_then(|m| m.as_str())
+            .is_some_and(|m| m.starts_with("system."))
+}
+
+/// replay feeds the requests recorded in journal back through the dispatcher.  Sessions are
+/// renamed so they cannot collide with live ones and every index directory named in the journal
+/// is replaced with a fresh directory under `directory`, in-memory indexes stay in memory.
+/// Entries answered with a different code than the one recorded are reported as mismatches.
+pub fn replay(journal: &Path, directory: &Path) -> InternalCallResult<ReplayReport> {
+    let file = File::open(journal)
+        .map_err(|e| ErrorKinds::IO(format!("failed to open journal {} {e}", journal.display())))?;
+    let mut replayer = Replayer {
+        directory: directory.to_path_buf(),
+        sessions: HashMap::new(),
+        directories: HashMap::new(),
+    };
+    let mut report = ReplayReport::default();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| ErrorKinds::IO(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut entry: JournalEntry = match serde_json::from_str(&line) {
+            Ok(e) => e,
+            Err(e) => {
+                return make_internal_json_error(ErrorKinds::Parse(format!(
+                    "journal line {} {e}",
+                    n + 1
+                )))
+            }
+        };
+        if is_system_request(&entry.request) {
+            report.skipped += 1;
+            continue;
+        }
+        let input = match &entry.request {
+            serde_json::Value::String(raw) => raw.clone(),
+            _ => {
+                replayer.rewrite(&mut entry.request)?;
+                entry.request.to_string()
+            }
+        };
+        let (code, response) = dispatch_request(&input);
+        debug!("replayed line {} code={code}", n + 1);
+        report.replayed += 1;
+        if code != entry.code {
+            report.mismatches.push(ReplayMismatch {
+                line: n + 1,
+                expected: entry.code,
+                got: code,
+                response,
+            });
+        }
+    }
+    for id in replayer.sessions.values() {
+        let _ = crate::do_term(id);
+    }
+    Ok(report)
+}
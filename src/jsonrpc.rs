@@ -0,0 +1,238 @@
+use crate::call_global;
+use crate::debug;
+use crate::error_data;
+use crate::error_field;
+use crate::is_global_obj;
+use crate::is_session_obj;
+use crate::metrics;
+use crate::with_session;
+use crate::ErrorKinds;
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+// Error codes reserved by the JSON-RPC 2.0 specification
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const SERVER_ERROR: i64 = -32000;
+
+/// JSON-RPC 2.0 representation of an incoming client request.  `method` is addressed as
+/// `<obj>.<method>` (e.g. `searcher.search`) and `session` names the TantivySession the call
+/// runs against, leaving `id` free to identify the individual call.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: serde_json::Value,
+    #[serde(default)]
+    pub session: Option<String>,
+    pub method: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+/// top_level_jsonrpc tells whether input that failed to parse carries a jsonrpc key at the top
+/// level of its request, or of the first request of a batch, so its parse error can be answered
+/// in the envelope a parsed request would have been routed to.  The input is scanned only as far
+/// as it goes, keys nested in params are not considered.
+pub fn top_level_jsonrpc(input: &str) -> bool {
+    let key_depth = if input.trim_start().starts_with('[') {
+        2
+    } else {
+        1
+    };
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    let mut last_string: Option<&str> = None;
+    for (i, c) in input.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+                last_string = Some(&input[start..i]);
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                start = i + 1;
+            }
+            '{' | '[' => {
+                depth += 1;
+                last_string = None;
+            }
+            '}' | ']' => {
+                depth -= 1;
+                last_string = None;
+            }
+            ':' if depth == key_depth && last_string == Some("jsonrpc") => return true,
+            c if c.is_whitespace() => {}
+            _ => last_string = None,
+        }
+    }
+    false
+}
+
+/// make_rpc_error builds a JSON-RPC 2.0 error response
+/// # Arguments
+/// * `id`- the request id the error answers, null if it could not be determined
+/// * `code`- the JSON-RPC error code
+/// * `message`- human readable description of the error
+pub fn make_rpc_error(id: &serde_json::Value, code: i64, message: &str) -> serde_json::Value {
+    debug!("rpc error={} code={}", message, code);
+    json!({
+        "jsonrpc": JSONRPC_VERSION,
+        "id": id,
+        "error": {
+            "code": code,
+            "message": message,
+        },
+    })
+}
+
+/// make_rpc_result builds a JSON-RPC 2.0 success response
+pub fn make_rpc_result(id: &serde_json::Value, result: serde_json::Value) -> serde_json::Value {
+    json!({
+        "jsonrpc": JSONRPC_VERSION,
+        "id": id,
+        "result": result,
+    })
+}
+
+/// make_rpc_kind_error builds a JSON-RPC 2.0 error response for ek, reporting the kind of the
+/// error alongside the request context in the data member
+pub fn make_rpc_kind_error(
+    id: &serde_json::Value,
+    ek: &ErrorKinds,
+    mut data: serde_json::Value,
+) -> serde_json::Value {
+    let mut resp = make_rpc_error(id, ek.code(), &ek.to_string());
+    if !data.is_object() {
+        data = json!({});
+    }
+    data["kind"] = json!(ek.kind());
+    if let Some(d) = ek.details() {
+        data["diff"] = d.clone();
+    }
+    resp["error"]["data"] = data;
+    resp
+}
+
+// buffer_to_result turns a handler's return buffer into a JSON value for the response.  Handlers
+// write JSON, anything else is passed back as a string.
+pub fn buffer_to_result(buf: &str) -> serde_json::Value {
+    if buf.is_empty() {
+        return serde_json::Value::Null;
+    }
+    serde_json::from_str(buf).unwrap_or_else(|_| serde_json::Value::String(buf.to_string()))
+}
+
+/// process_value runs an already parsed JSON-RPC 2.0 request and returns the status code and
+/// response object.  A notification, a valid request without an id, is run but gets no response.
+pub fn process_value(v: serde_json::Value) -> (i64, Option<serde_json::Value>) {
+    let notification = v.get("id").is_none();
+    let id = v.get("id").cloned().unwrap_or(serde_json::Value::Null);
+    let req: RpcRequest = match serde_json::from_value(v) {
+        Ok(r) => r,
+        Err(e) => {
+            let ek = ErrorKinds::InvalidRequest(e.to_string());
+            return (-1, Some(make_rpc_kind_error(&id, &ek, json!({}))));
+        }
+    };
+    if req.jsonrpc != JSONRPC_VERSION {
+        let ek = ErrorKinds::InvalidRequest(format!("unsupported jsonrpc version {}", req.jsonrpc));
+        return (-1, Some(make_rpc_kind_error(&req.id, &ek, json!({}))));
+    }
+    let name = req.method.clone();
+    let (code, resp) = run_request(req);
+    if notification {
+        debug!("notification run code={code}");
+        return (code, None);
+    }
+    if code >= 0 {
+        let (obj, method) = name.split_once('.').unwrap_or((&name, ""));
+        metrics::record_response(obj, method, metrics::json_len(&resp));
+    }
+    (code, Some(resp))
+}
+
+// run_request runs a valid JSON-RPC 2.0 request against its session or the global objects
+fn run_request(req: RpcRequest) -> (i64, serde_json::Value) {
+    let (obj, method) = match req.method.split_once('.') {
+        Some((o, m)) if is_global_obj(o) => {
+            let id = req.session.as_deref().unwrap_or_default();
+            return match call_global(id, o, m, &req.params) {
+                Ok(v) => (0, make_rpc_result(&req.id, v)),
+                Err(e) => (-1, make_rpc_kind_error(&req.id, &e, error_data(o, m, None))),
+            };
+        }
+        Some((o, m)) if is_session_obj(o) => (o, m),
+        _ => {
+            let ek = ErrorKinds::UnRecognizedCommand(req.method.clone());
+            metrics::record(&req.method, "", Duration::ZERO, Some(&ek));
+            let data = json!({ "method": req.method });
+            return (-1, make_rpc_kind_error(&req.id, &ek, data));
+        }
+    };
+    let session_id = match req.session.as_deref() {
+        Some(s) if !s.is_empty() => s,
+        _ => {
+            let ek = ErrorKinds::InvalidRequest("session must be specified".to_string());
+            return (-1, make_rpc_kind_error(&req.id, &ek, json!({})));
+        }
+    };
+    let field = error_field(&req.params);
+    let run = with_session(session_id, |entity| {
+        entity.return_buffer.clear();
+        entity
+            .call_handler(method, obj, req.params)
+            .map(|_| buffer_to_result(&entity.return_buffer))
+    });
+    match run.and_then(|r| r) {
+        Ok(result) => (0, make_rpc_result(&req.id, result)),
+        Err(e) => (
+            -1,
+            make_rpc_kind_error(&req.id, &e, error_data(obj, method, field)),
+        ),
+    }
+}
+
+/// process parses a raw JSON-RPC 2.0 envelope and runs it, returning the status code and the
+/// serialized response, empty for a notification
+pub fn process(input: &str) -> (i64, String) {
+    let (code, resp) = match serde_json::from_str::<serde_json::Value>(input) {
+        Ok(v) => process_value(v),
+        Err(e) => {
+            let ek = ErrorKinds::Parse(e.to_string());
+            (
+                -1,
+                Some(make_rpc_kind_error(
+                    &serde_json::Value::Null,
+                    &ek,
+                    json!({}),
+                )),
+            )
+        }
+    };
+    let out = match resp.map(|r| serde_json::to_string(&r)) {
+        Some(Ok(x)) => x,
+        Some(Err(err)) => format!("{err}"),
+        None => String::new(),
+    };
+    (code, out)
+}
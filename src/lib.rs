@@ -16,25 +16,37 @@ use tantivy::{Searcher, TantivyError};
 
 use chrono::format::ParseError;
 use lazy_static::lazy_static;
-use std::sync::Mutex;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use tantivy::tokenizer::*;
 
 extern crate thiserror;
 use thiserror::Error;
 
 lazy_static! {
-    static ref TANTIVY_MAP: Mutex<HashMap<String, TantivySession>> = Mutex::new(HashMap::new());
+    static ref TANTIVY_MAP: Mutex<HashMap<String, Arc<Mutex<TantivySession>>>> =
+        Mutex::new(HashMap::new());
     static ref ERRORS: Mutex<HashMap<String, Vec<String>>> = Mutex::new(HashMap::new());
     static ref DATA_MAP: Mutex<HashMap<i64, XferData>> = Mutex::new(HashMap::new());
 }
 
+pub mod ingest;
+pub mod journal;
+pub mod jsonrpc;
+pub mod logging;
+pub mod metrics;
+pub mod server;
 pub mod tokenizer;
 pub mod tsession_builder;
 pub mod tsession_document;
 pub mod tsession_index;
 pub mod tsession_query_parser;
+pub mod tsession_registry;
 pub mod tsession_schema;
 pub mod tsession_searcher;
+pub mod tsession_system;
 pub mod tsession_tests;
 
 pub use self::tokenizer::*;
@@ -48,7 +60,9 @@ pub use self::tsession_tests::*;
 // conversation based on the TantivySession::id.
 struct TantivySession {
     pub(crate) id: String,
+    // pending documents keyed by their doc_id, handles come from next_doc_id and are never reused
     pub(crate) doc: Option<HashMap<usize, tantivy::Document>>,
+    pub(crate) next_doc_id: usize,
     pub(crate) builder: Option<Box<tantivy::schema::SchemaBuilder>>,
     pub(crate) schema: Option<tantivy::schema::Schema>,
     pub(crate) index: Option<Box<tantivy::Index>>,
@@ -59,6 +73,7 @@ struct TantivySession {
     pub(crate) dyn_q: Option<Box<dyn Query>>,
     pub(crate) fuzzy_q: Option<Box<FuzzyTermQuery>>,
     pub(crate) tokenizer_manager: Option<TokenizerManager>,
+    pub(crate) last_used: Instant,
 
     return_buffer: String,
     memsize: u64,
@@ -67,6 +82,7 @@ struct TantivySession {
 #[derive(Clone)]
 pub struct XferData {
     pub bytes: Vec<u8>,
+    pub created: Instant,
 }
 
 impl TantivySession {
@@ -74,6 +90,7 @@ impl TantivySession {
         TantivySession {
             id: id.to_string(),
             doc: None,
+            next_doc_id: 1,
             builder: None,
             schema: None,
             index: None,
@@ -84,6 +101,7 @@ impl TantivySession {
             dyn_q: None,
             fuzzy_q: None,
             tokenizer_manager: None,
+            last_used: Instant::now(),
             return_buffer: String::new(),
             memsize: crate::tsession_index::DEFAULT_INDEX_WRITER_MEM_SIZE,
         }
@@ -107,71 +125,137 @@ impl TantivySession {
         };
     }
 
+    /// make_json_error_kind is make_json_error carrying the code, kind and context of the error
+    /// # Arguments
+    /// * `err`- the message to be returned to the client
+    /// * `ek`- the error the message was produced from
+    /// * `data`- request context reported with the error
+    pub fn make_json_error_kind(&mut self, err: &str, ek: &ErrorKinds, data: serde_json::Value) {
+        self.return_buffer = make_json_error_kind(err, &self.id, ek, data);
+    }
+
     // do_method is a translation from a string json method to an actual call.  All json params are passed
     pub fn do_method(&mut self, method: &str, obj: &str, params: serde_json::Value) -> i64 {
         debug!("In do_method");
-        match obj {
-            "query_parser" => {
-                if let Err(e) = self.handle_query_parser(method, params) {
-                    self.make_json_error(&format!("handle query parser error={e}"));
-                    return -1;
-                };
-            }
-            "searcher" => {
-                if let Err(e) = self.handle_searcher(method, params) {
-                    self.make_json_error(&format!("handle searcher error={e}"));
-                    return -1;
-                };
-            }
-            "fuzzy_searcher" => {
-                if let Err(e) = self.handle_fuzzy_searcher(method, params) {
-                    self.make_json_error(&format!("handle searcher error={e}"));
-                    return -1;
-                };
-            }
-            "index" => {
-                if let Err(e) = self.handle_index(method, params) {
-                    self.make_json_error(&format!("handle index error={e}"));
-                    return -1;
-                };
-            }
-            "indexwriter" => {
-                if let Err(e) = self.handle_index_writer(method, params) {
-                    self.make_json_error(&format!("handle index writer error={e}"));
-                    return -1;
-                };
-            }
-            "index_reader" => {
-                if let Err(e) = self.handle_index_reader(method, params) {
-                    self.make_json_error(&format!("handle_index_reader error={e}"));
-                    return -1;
-                };
-            }
-            "document" => {
-                if let Err(e) = self.handle_document(method, params) {
-                    self.make_json_error(&format!("handle document error={e}"));
-                    return -1;
-                };
-            }
-            "builder" => {
-                if let Err(e) = self.handler_builder(method, params) {
-                    self.make_json_error(&format!("handle builder error={e}"));
-                    return -1;
-                };
-            }
-            "schema" => {
-                if let Err(e) = self.handler_schema(method, params) {
-                    self.make_json_error(&format!("handle schema error={e}"));
-                    return -1;
-                };
-            }
-            &_ => {}
-        };
-        let _ = &self.doc;
-        let _ = &self.builder;
+        let field = error_field(&params);
+        if let Err(e) = self.call_handler(method, obj, params) {
+            self.make_json_error_kind(
+                &format!("{} error={e}", handler_context(obj)),
+                &e,
+                error_data(obj, method, field),
+            );
+            return -1;
+        }
         0
     }
+
+    // call_handler routes a request to the handler for obj and hands back the raw result so each
+    // protocol can render errors in its own envelope.  Panics in the handler are returned as
+    // errors and every call is counted in the metrics.
+    pub fn call_handler(
+        &mut self,
+        method: &str,
+        obj: &str,
+        params: serde_json::Value,
+    ) -> InternalCallResult<u32> {
+        self.last_used = Instant::now();
+        let res = catch_panic(|| self.dispatch_handler(method, obj, params)).and_then(|r| r);
+        metrics::record(obj, method, self.last_used.elapsed(), res.as_ref().err());
+        res
+    }
+
+    fn dispatch_handler(
+        &mut self,
+        method: &str,
+        obj: &str,
+        params: serde_json::Value,
+    ) -> InternalCallResult<u32> {
+        tsession_system::check_method(obj, method)?;
+        match obj {
+            "query_parser" => self.handle_query_parser(method, params),
+            "searcher" => self.handle_searcher(method, params),
+            "fuzzy_searcher" => self.handle_fuzzy_searcher(method, params),
+            "index" => self.handle_index(method, params),
+            "indexwriter" => self.handle_index_writer(method, params),
+            "index_reader" => self.handle_index_reader(method, params),
+            "document" => self.handle_document(method, params),
+            "builder" => self.handler_builder(method, params),
+            "schema" => self.handler_schema(method, params),
+            &_ => Err(ErrorKinds::UnRecognizedCommand(obj.to_string())),
+        }
+    }
+}
+
+// handler_context is the prefix used for 1.0 error messages produced by the handler for obj.
+fn handler_context(obj: &str) -> &'static str {
+    match obj {
+        "query_parser" => "handle query parser",
+        "searcher" | "fuzzy_searcher" => "handle searcher",
+        "index" => "handle index",
+        "indexwriter" => "handle index writer",
+        "index_reader" => "handle_index_reader",
+        "document" => "handle document",
+        "builder" => "handle builder",
+        "schema" => "handle schema",
+        "session" => "handle session",
+        "system" => "handle system",
+        &_ => "handle",
+    }
 }
+
+// error_field picks the field a request refers to so it can be reported if the request fails
+fn error_field(params: &serde_json::Value) -> Option<serde_json::Value> {
+    params.get("field").or_else(|| params.get("name")).cloned()
+}
+
+// error_data collects the request context reported in the data member of an error
+fn error_data(obj: &str, method: &str, field: Option<serde_json::Value>) -> serde_json::Value {
+    let mut data = json!({ "obj": obj, "method": method });
+    if let Some(f) = field {
+        data["field"] = f;
+    }
+    data
+}
+
+// is_global_obj reports whether obj is served without a TantivySession.
+fn is_global_obj(obj: &str) -> bool {
+    matches!(obj, "session" | "system")
+}
+
+// call_global runs a request against one of the global objects, id names the session of the
+// request when there is one.
+fn call_global(
+    id: &str,
+    obj: &str,
+    method: &str,
+    params: &serde_json::Value,
+) -> InternalCallResult<serde_json::Value> {
+    let start = Instant::now();
+    let res = tsession_system::check_method(obj, method).and_then(|_| match obj {
+        "session" => tsession_registry::handle_session(id, method, params),
+        "system" => tsession_system::handle_system(method, params),
+        &_ => Err(ErrorKinds::UnRecognizedCommand(format!("{obj}.{method}"))),
+    });
+    metrics::record(obj, method, start.elapsed(), res.as_ref().err());
+    res
+}
+
+// is_session_obj reports whether obj is served by a TantivySession.
+fn is_session_obj(obj: &str) -> bool {
+    matches!(
+        obj,
+        "document"
+            | "builder"
+            | "index"
+            | "indexwriter"
+            | "query_parser"
+            | "searcher"
+            | "index_reader"
+            | "fuzzy_searcher"
+            | "schema"
+    )
+}
+
 /// Bitcode representation of a incomming client request
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Request<'a> {
@@ -202,6 +286,48 @@ pub fn make_json_error(err: &str, id: &str) -> String {
     vr
 }
 
+/// make_json_error_kind is make_json_error carrying the numeric code and kind of ek along with a
+/// data object holding the request context
+/// # Arguments
+/// * `err`- the message to be returned to the client
+/// * `id`- the session id the error is reported against
+/// * `ek`- the error the message was produced from
+/// * `data`- request context such as obj, method and field
+pub fn make_json_error_kind(
+    err: &str,
+    id: &str,
+    ek: &ErrorKinds,
+    data: serde_json::Value,
+) -> String {
+    match serde_json::to_string(&json_error_value(err, id, ek, data)) {
+        Ok(x) => x,
+        Err(err) => format!("{err}"),
+    }
+}
+
+// json_error_value builds the jpc 1.0 error object rendered by make_json_error_kind
+fn json_error_value(
+    err: &str,
+    id: &str,
+    ek: &ErrorKinds,
+    mut data: serde_json::Value,
+) -> serde_json::Value {
+    debug!("error={} kind={}", err, ek.kind());
+    if let Some(d) = ek.details().filter(|_| data.is_object()) {
+        data["diff"] = d.clone();
+    }
+    json!(
+        {
+        "error" :  err,
+        "jpc" : "1.0",
+        "id"  : id,
+        "code" : ek.code(),
+        "kind" : ek.kind(),
+        "data" : data,
+        }
+    )
+}
+
 pub fn make_internal_json_error<T>(ek: ErrorKinds) -> InternalCallResult<T> {
     debug!("error={ek}");
     Err(ek)
@@ -233,6 +359,69 @@ pub enum ErrorKinds {
     BadParams(String),
     #[error("Search : `{0}`")]
     Search(String),
+    #[error("Parse : `{0}`")]
+    Parse(String),
+    #[error("InvalidRequest : `{0}`")]
+    InvalidRequest(String),
+    #[error("Panic : `{0}`")]
+    Panic(String),
+    #[error("SchemaMismatch : `{0}`")]
+    SchemaMismatch(serde_json::Value),
+}
+
+impl ErrorKinds {
+    /// code is the stable numeric code reported to clients for the error.  Codes follow the
+    /// JSON-RPC 2.0 conventions, errors specific to this library use the -32000 to -32099 range.
+    pub fn code(&self) -> i64 {
+        match self {
+            ErrorKinds::Other(_) => -32000,
+            ErrorKinds::Permission(_) => -32001,
+            ErrorKinds::IO(_) => -32002,
+            ErrorKinds::Utf8Error(_) => -32003,
+            ErrorKinds::NotExist(_) => -32004,
+            ErrorKinds::IsDir(_) => -32005,
+            ErrorKinds::NotDir(_) => -32006,
+            ErrorKinds::BadInitialization(_) => -32007,
+            ErrorKinds::NotFinalized(_) => -32008,
+            ErrorKinds::Search(_) => -32009,
+            ErrorKinds::Panic(_) => -32010,
+            ErrorKinds::SchemaMismatch(_) => -32011,
+            ErrorKinds::UnRecognizedCommand(_) => jsonrpc::METHOD_NOT_FOUND,
+            ErrorKinds::BadParams(_) => jsonrpc::INVALID_PARAMS,
+            ErrorKinds::Parse(_) => jsonrpc::PARSE_ERROR,
+            ErrorKinds::InvalidRequest(_) => jsonrpc::INVALID_REQUEST,
+        }
+    }
+
+    /// kind is the machine readable name of the error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ErrorKinds::Other(_) => "Other",
+            ErrorKinds::UnRecognizedCommand(_) => "UnRecognizedCommand",
+            ErrorKinds::Permission(_) => "Permission",
+            ErrorKinds::IO(_) => "IO",
+            ErrorKinds::Utf8Error(_) => "Utf8Error",
+            ErrorKinds::NotExist(_) => "NotExist",
+            ErrorKinds::IsDir(_) => "IsDir",
+            ErrorKinds::NotDir(_) => "NotDir",
+            ErrorKinds::BadInitialization(_) => "BadInitialization",
+            ErrorKinds::NotFinalized(_) => "NotFinalized",
+            ErrorKinds::BadParams(_) => "BadParams",
+            ErrorKinds::Search(_) => "Search",
+            ErrorKinds::Parse(_) => "Parse",
+            ErrorKinds::InvalidRequest(_) => "InvalidRequest",
+            ErrorKinds::Panic(_) => "Panic",
+            ErrorKinds::SchemaMismatch(_) => "SchemaMismatch",
+        }
+    }
+
+    /// details is structured information about the error reported as `diff` in the error data
+    pub fn details(&self) -> Option<&serde_json::Value> {
+        match self {
+            ErrorKinds::SchemaMismatch(diff) => Some(diff),
+            _ => None,
+        }
+    }
 }
 
 impl From<std::str::Utf8Error> for ErrorKinds {
@@ -271,6 +460,12 @@ impl From<ParseError> for ErrorKinds {
     }
 }
 
+impl From<tantivy::schema::DocParsingError> for ErrorKinds {
+    fn from(e: tantivy::schema::DocParsingError) -> Self {
+        ErrorKinds::BadParams(e.to_string())
+    }
+}
+
 impl From<std::net::AddrParseError> for ErrorKinds {
     fn from(e: std::net::AddrParseError) -> Self {
         ErrorKinds::BadParams(e.to_string())
@@ -279,40 +474,103 @@ impl From<std::net::AddrParseError> for ErrorKinds {
 
 pub type InternalCallResult<T> = Result<T, ErrorKinds>;
 
+/// catch_panic runs f and reports a panic as ErrorKinds::Panic instead of letting it unwind, a
+/// panic crossing the FFI boundary aborts the calling process
+pub fn catch_panic<R>(f: impl FnOnce() -> R) -> InternalCallResult<R> {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).map_err(|p| {
+        let msg = match p.downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => match p.downcast_ref::<String>() {
+                Some(s) => s.clone(),
+                None => "unknown panic".to_string(),
+            },
+        };
+        error!("caught panic {msg}");
+        ErrorKinds::Panic(msg)
+    })
+}
+
+// lock_recover locks m, recovering it if a panic poisoned it.  Panics are caught before they leave
+// the library, so the data is still usable and failing every later call would be worse.
+pub(crate) fn lock_recover<'a, T>(m: &'a Mutex<T>, name: &str) -> MutexGuard<'a, T> {
+    m.lock().unwrap_or_else(|e| {
+        error!("{name} lock poisoned, recovering");
+        m.clear_poison();
+        e.into_inner()
+    })
+}
+
 /// # Safety
 ///
 #[no_mangle]
 pub unsafe extern "C" fn init() -> u8 {
+    catch_panic(do_init).unwrap_or(1)
+}
+
+fn do_init() -> u8 {
     let mut log_level: &str = "info";
     let parse_val: String;
     if let Ok(existing_value) = std::env::var("ELV_RUST_LOG") {
         parse_val = existing_value;
         log_level = &parse_val;
     }
-    let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level))
-        .try_init();
+    logging::install(log_level);
+    if let Ok(path) = std::env::var(journal::JOURNAL_ENV) {
+        if let Err(e) = journal::start(std::path::Path::new(&path)) {
+            error!("journal not started {e}");
+        }
+    }
+    if let Ok(ttl) = std::env::var(tsession_registry::SESSION_IDLE_TTL_ENV) {
+        match ttl.parse::<u64>() {
+            Ok(secs) => tsession_registry::set_idle_ttl(secs),
+            Err(e) => error!(
+                "invalid {} {ttl} {e}",
+                tsession_registry::SESSION_IDLE_TTL_ENV
+            ),
+        }
+    }
     0
 }
 
 pub fn test_init() {
-    let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("trace"))
-        .try_init();
+    logging::install("trace");
+}
+
+/// # Safety
+///
+/// set_log_callback sends log records to cb instead of stderr, keeping those at or above level,
+/// from 0 (off) to 5 (trace).  A null cb sends logs back to stderr.  cb may be called from any
+/// thread.  Returns -1 for an unknown level.
+#[no_mangle]
+pub unsafe extern "C" fn set_log_callback(cb: logging::LogCallback, level: i32) -> i8 {
+    match logging::level_filter(level) {
+        Some(l) => match catch_panic(|| logging::set_callback(cb, l)) {
+            Ok(_) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
+    }
+}
+
+/// # Safety
+///
+/// set_log_level changes the level log records are kept at, from 0 (off) to 5 (trace).  Call it
+/// after init or set_log_callback, both set the level when they install the logger.  Logs written to
+/// stderr are still limited by ELV_RUST_LOG.  Returns -1 for an unknown level.
+#[no_mangle]
+pub unsafe extern "C" fn set_log_level(level: i32) -> i8 {
+    match logging::level_filter(level) {
+        Some(l) => match catch_panic(|| log::set_max_level(l)) {
+            Ok(_) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
+    }
 }
 
 fn do_term(s: &str) -> InternalCallResult<String> {
-    match TANTIVY_MAP.lock().as_mut() {
-        Ok(t) => {
-            debug!("removing {s}");
-            t.remove_entry(s)
-                .ok_or(ErrorKinds::NotExist(format!("Entry {s} is not available")))
-        }
-        Err(e) => {
-            info!("TANTIVY_MAP lock failed {e}");
-            return Err(ErrorKinds::BadParams(format!(
-                "TANTIVY_MAP lock failed e = {e}"
-            )));
-        }
-    }?;
+    debug!("removing {s}");
+    tsession_registry::remove_session(s)?;
     Ok(s.to_string())
 }
 
@@ -322,9 +580,12 @@ fn do_term(s: &str) -> InternalCallResult<String> {
 ///
 #[no_mangle]
 pub unsafe extern "C" fn term(s: *const c_char) -> i8 {
+    if s.is_null() {
+        return 0;
+    }
     let c_str = CStr::from_ptr(s).to_str().unwrap_or("");
     if !c_str.is_empty() {
-        match do_term(c_str) {
+        match catch_panic(|| do_term(c_str)).and_then(|r| r) {
             Ok(_) => {
                 debug!("tag cleaned");
                 0
@@ -345,8 +606,10 @@ pub unsafe extern "C" fn term(s: *const c_char) -> i8 {
 /// current instance is loaded and will reset on a new invocation of tantivy
 #[no_mangle]
 pub unsafe extern "C" fn set_k_and_b(k: f32, b: f32) -> i8 {
-    tantivy::query::do_set_k_and_b(k, b);
-    0
+    match catch_panic(|| tantivy::query::do_set_k_and_b(k, b)) {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
 }
 
 #[test]
@@ -357,21 +620,109 @@ fn test_kb() {
 }
 /// # Safety
 ///
+/// free_data releases the response held under handle, including the negative handle of an error
+/// response from tantivy_jpc.  Returns -1 when the handle is unknown.
 #[no_mangle]
 pub unsafe extern "C" fn free_data(handle: i64) -> std::ffi::c_int {
-    let mut map = match DATA_MAP.lock() {
-        Ok(m) => m,
-        Err(e) => {
-            error!("free_data failed to lock data map err={e}");
+    catch_panic(|| {
+        let mut map = lock_recover(&DATA_MAP, "DATA_MAP");
+        match map.remove(&handle.wrapping_abs()) {
+            Some(data) => {
+                drop(data);
+                0 // success
+            }
+            None => -1, // error: handle not found in the map
+        }
+    })
+    .unwrap_or(-1)
+}
+
+// handles are handed out in order and never reused.  Error responses are returned under their
+// negated handle, so handles start at 2 to keep -1 a plain failure with nothing held.
+static NEXT_HANDLE: AtomicI64 = AtomicI64::new(2);
+
+// park_data stores bytes in DATA_MAP until the caller frees them, returning their handle
+fn park_data(map: &mut HashMap<i64, XferData>, bytes: Vec<u8>) -> i64 {
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    map.insert(
+        handle,
+        XferData {
+            bytes,
+            created: Instant::now(),
+        },
+    );
+    handle
+}
+
+/// data_map_stats reports the responses still held for callers: how many, their total size and
+/// the age of the oldest.  With `reclaim_after` set, responses held longer than that are dropped
+/// first.  A reclaimed response must not be in use, so the age should be far above the time any
+/// caller takes to copy a response.
+pub fn data_map_stats(reclaim_after: Option<std::time::Duration>) -> serde_json::Value {
+    let mut map = lock_recover(&DATA_MAP, "DATA_MAP");
+    let mut reclaimed = 0;
+    let mut reclaimed_bytes = 0;
+    if let Some(age) = reclaim_after {
+        map.retain(|handle, data| {
+            if data.created.elapsed() < age {
+                return true;
+            }
+            debug!("reclaiming handle {handle}");
+            reclaimed += 1;
+            reclaimed_bytes += data.bytes.len();
+            false
+        });
+    }
+    if reclaimed > 0 {
+        info!("reclaimed {reclaimed} responses holding {reclaimed_bytes} bytes");
+    }
+    json!({
+        "handles": map.len(),
+        "bytes": map.values().map(|d| d.bytes.len()).sum::<usize>(),
+        "oldest_secs": map.values().map(|d| d.created.elapsed().as_secs_f64()).fold(0.0, f64::max),
+        "reclaimed": reclaimed,
+        "reclaimed_bytes": reclaimed_bytes,
+    })
+}
+
+/// # Safety
+///
+/// read_data copies the response held under handle into `out`, a buffer of `out_cap` bytes, and
+/// releases it.  `out_len` receives the size of the response.  Returns 0 once copied, or -1 when
+/// the handle is unknown or the buffer is still too small, in which case the response is kept.
+#[no_mangle]
+pub unsafe extern "C" fn read_data(
+    handle: i64,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> std::ffi::c_int {
+    catch_panic(|| {
+        let handle = handle.wrapping_abs();
+        let mut map = lock_recover(&DATA_MAP, "DATA_MAP");
+        let len = match map.get(&handle) {
+            Some(data) => data.bytes.len(),
+            None => {
+                *out_len = 0;
+                return -1;
+            }
+        };
+        *out_len = len;
+        if len > out_cap {
             return -1;
         }
-    };
-    match map.remove(&handle) {
-        Some(data) => {
-            drop(data);
-            0 // success
+        if let Some(data) = map.remove(&handle) {
+            copy_out(&data.bytes, out);
         }
-        None => -1, // error: handle not found in the map
+        0
+    })
+    .unwrap_or(-1)
+}
+
+// copy_out copies bytes into out, which the caller guarantees holds at least bytes.len()
+unsafe fn copy_out(bytes: &[u8], out: *mut u8) {
+    if !bytes.is_empty() {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
     }
 }
 
@@ -382,19 +733,8 @@ unsafe fn send_to_golang(
     go_memory_sz: *mut usize,
     do_return_val: i64,
 ) -> i64 {
-    let mut map = match DATA_MAP.lock() {
-        Ok(l) => l,
-        Err(e) => {
-            error!("failed to lock DATA-MAP {e}");
-            return -1;
-        }
-    };
-    let mut handle = rand::random::<i64>();
-    if handle < 0 {
-        handle = handle * -1;
-    }
-    let data = XferData { bytes: val_to_send };
-    map.insert(handle, data);
+    let mut map = lock_recover(&DATA_MAP, "DATA_MAP");
+    let handle = park_data(&mut map, val_to_send);
     let mem = match map.get(&handle) {
         Some(m) => m,
         None => {
@@ -405,12 +745,282 @@ unsafe fn send_to_golang(
     *go_memory = mem.bytes.as_ptr() as *mut u8;
     *go_memory_sz = mem.bytes.len();
     if do_return_val < 0 {
-        do_return_val
+        -handle
     } else {
         handle
     }
 }
 
+// new_session creates the TantivySession for id along with the tokenizers every index registers.
+fn new_session(id: &str) -> TantivySession {
+    let stop_words = vec![
+        "a".to_string(),
+        "an".to_string(),
+        "and".to_string(),
+        "are".to_string(),
+        "as".to_string(),
+        "at".to_string(),
+        "be".to_string(),
+        "but".to_string(),
+        "by".to_string(),
+        "for".to_string(),
+        "if".to_string(),
+        "in".to_string(),
+        "into".to_string(),
+        "is".to_string(),
+        "it".to_string(),
+        "no".to_string(),
+        "not".to_string(),
+        "of".to_string(),
+        "on".to_string(),
+        "or".to_string(),
+        "such".to_string(),
+        "that".to_string(),
+        "the".to_string(),
+        "their".to_string(),
+        "then".to_string(),
+        "there".to_string(),
+        "these".to_string(),
+        "they".to_string(),
+        "this".to_string(),
+        "to".to_string(),
+        "was".to_string(),
+        "will".to_string(),
+        "with".to_string(),
+    ];
+    let stops = match StopWordFilter::new(Language::English) {
+        Some(swf) => swf,
+        None => StopWordFilter::remove(stop_words),
+    };
+    let tokenizer_manager = TokenizerManager::default();
+    tokenizer_manager.register(
+        "en_stem_with_stop_words",
+        TextAnalyzer::builder(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(40))
+            .filter(LowerCaser)
+            .filter(stops.clone())
+            .filter(Stemmer::new(Language::English))
+            .build(),
+    );
+    tokenizer_manager.register(
+        "filename",
+        TextAnalyzer::builder(CamelCaseDigitTokenizer)
+            .filter(RemoveLongFilter::limit(40))
+            .filter(LowerCaser)
+            .filter(stops.clone())
+            .filter(Stemmer::new(Language::English))
+            .build(),
+    );
+    let mut te = TantivySession::new(id);
+    te.tokenizer_manager = Some(tokenizer_manager);
+    te
+}
+
+// get_session returns the session for id, creating it on first use.  TANTIVY_MAP is only held
+// while the entry is looked up, callers lock the returned session for as long as they work on it
+// so requests against different sessions run concurrently.
+fn get_session(id: &str) -> InternalCallResult<Arc<Mutex<TantivySession>>> {
+    tsession_registry::evict_idle_sessions(Some(id));
+    let mut tm = lock_recover(&TANTIVY_MAP, "TANTIVY_MAP");
+    Ok(tm
+        .entry(id.to_string())
+        .or_insert_with(|| Arc::new(Mutex::new(new_session(id))))
+        .clone())
+}
+
+// with_session runs f on the locked session for id.  An idle session can be evicted and closed
+// between get_session handing it out and the lock being taken, so once locked the session is
+// checked to still be the registered one and the lookup is repeated when it is not.
+fn with_session<R>(id: &str, f: impl FnOnce(&mut TantivySession) -> R) -> InternalCallResult<R> {
+    let session = get_session(id)?;
+    let mut entity = lock_recover(&session, id);
+    let registered = lock_recover(&TANTIVY_MAP, "TANTIVY_MAP")
+        .get(id)
+        .is_some_and(|s| Arc::ptr_eq(s, &session));
+    if registered {
+        return Ok(f(&mut entity));
+    }
+    drop(entity);
+    debug!("session {id} was evicted before it could be locked, looking it up again");
+    with_session(id, f)
+}
+
+/// process_request runs a request envelope through the dispatcher and returns the status code
+/// along with the response to hand back to the caller.  Envelopes carrying a `jsonrpc` member are
+/// answered as JSON-RPC 2.0, everything else as jpc 1.0.  A JSON array is treated as a batch.
+/// The request is recorded in the journal when journaling is enabled.
+pub fn process_request(input: &str) -> (i64, String) {
+    let (code, response) = dispatch_request(input);
+    journal::record(input, code);
+    (code, response)
+}
+
+// dispatch_request is process_request without the journal, replay uses it directly.  The input
+// is parsed once and the envelope picked from its top level, so a jsonrpc key inside the params
+// of a jpc 1.0 request does not change how it is run.
+fn dispatch_request(input: &str) -> (i64, String) {
+    let v: serde_json::Value = match serde_json::from_str(input) {
+        Ok(v) => v,
+        Err(err) => {
+            let ek = ErrorKinds::Parse(err.to_string());
+            if jsonrpc::top_level_jsonrpc(input) {
+                let resp = jsonrpc::make_rpc_kind_error(&serde_json::Value::Null, &ek, json!({}));
+                return (-1, resp.to_string());
+            }
+            let context = if input.trim_start().starts_with('[') {
+                "parse failed for batch"
+            } else {
+                "parse failed for http"
+            };
+            return (
+                -1,
+                make_json_error_kind(context, "ID not found", &ek, json!({})),
+            );
+        }
+    };
+    if let serde_json::Value::Array(items) = v {
+        return run_batch(items);
+    }
+    if v.get("jsonrpc").is_some() {
+        let (code, resp) = jsonrpc::process_value(v);
+        return (code, resp.map_or_else(String::new, |r| r.to_string()));
+    }
+    let json_params: Request = match <Request as serde::Deserialize>::deserialize(&v) {
+        Ok(m) => m,
+        Err(err) => {
+            let msg = make_json_error_kind(
+                "parse failed for http",
+                "ID not found",
+                &ErrorKinds::Parse(err.to_string()),
+                json!({}),
+            );
+            return (-1, msg);
+        }
+    };
+    run_jpc(
+        json_params.id,
+        json_params.obj,
+        json_params.method,
+        json_params.params,
+    )
+}
+
+// run_jpc executes a jpc 1.0 request against its session and returns the status code and buffer.
+// The size of a successful response is recorded from the buffer returned, handlers that leave
+// return_buffer alone hand back what it already held.
+fn run_jpc(id: &str, obj: &str, method: &str, params: serde_json::Value) -> (i64, String) {
+    let (code, buf) = run_jpc_call(id, obj, method, params);
+    if code >= 0 {
+        metrics::record_response(obj, method, buf.len());
+    }
+    (code, buf)
+}
+
+fn run_jpc_call(id: &str, obj: &str, method: &str, params: serde_json::Value) -> (i64, String) {
+    if is_global_obj(obj) {
+        return match call_global(id, obj, method, &params) {
+            Ok(v) => (0, v.to_string()),
+            Err(ek) => {
+                let msg = format!("{} error={ek}", handler_context(obj));
+                let data = error_data(obj, method, None);
+                (-1, make_json_error_kind(&msg, id, &ek, data))
+            }
+        };
+    }
+    if !is_session_obj(obj) {
+        let ek = ErrorKinds::UnRecognizedCommand(method.to_string());
+        metrics::record(obj, method, Duration::ZERO, Some(&ek));
+        let msg = make_json_error_kind(&ek.to_string(), "noid", &ek, error_data(obj, method, None));
+        return (-1, msg);
+    }
+    let run = with_session(id, |entity| {
+        let dmr = entity.do_method(method, obj, params);
+        (dmr, entity.return_buffer.clone())
+    });
+    match run {
+        Ok(r) => r,
+        Err(ek) => (
+            -1,
+            make_json_error_kind(&ek.to_string(), id, &ek, json!({})),
+        ),
+    }
+}
+
+/// process_batch runs a JSON array of requests in order.  Each element may use either envelope
+/// and gets its own entry in the returned array: JSON-RPC 2.0 requests are answered with their
+/// usual response object, jpc 1.0 requests with `{ "id", "jpc", "result" }` on success or the
+/// usual error object on failure.  A failing element does not stop the batch, so the status code
+/// only reports whether the batch itself could be run.
+pub fn process_batch(input: &str) -> (i64, String) {
+    match serde_json::from_str(input) {
+        Ok(items) => run_batch(items),
+        Err(e) => {
+            let ek = ErrorKinds::Parse(e.to_string());
+            let msg = if jsonrpc::top_level_jsonrpc(input) {
+                jsonrpc::make_rpc_kind_error(&serde_json::Value::Null, &ek, json!({})).to_string()
+            } else {
+                make_json_error_kind("parse failed for batch", "ID not found", &ek, json!({}))
+            };
+            (-1, msg)
+        }
+    }
+}
+
+// run_batch runs the already parsed elements of a batch, each picking its own envelope.  An
+// empty batch is answered with a single JSON-RPC 2.0 error and notifications get no entry, a batch
+// of notifications alone gets no response at all.
+fn run_batch(items: Vec<serde_json::Value>) -> (i64, String) {
+    if items.is_empty() {
+        let ek = ErrorKinds::InvalidRequest("empty batch".to_string());
+        let resp = jsonrpc::make_rpc_kind_error(&serde_json::Value::Null, &ek, json!({}));
+        return (-1, resp.to_string());
+    }
+    debug!("running batch of {}", items.len());
+    let results: Vec<serde_json::Value> = items
+        .into_iter()
+        .filter_map(|item| {
+            if item.get("jsonrpc").is_some() {
+                return jsonrpc::process_value(item).1;
+            }
+            let id = item.get("id").and_then(|x| x.as_str()).unwrap_or_default();
+            let (obj, method) = match (
+                item.get("obj").and_then(|x| x.as_str()),
+                item.get("method").and_then(|x| x.as_str()),
+            ) {
+                (Some(o), Some(m)) => (o, m),
+                _ => {
+                    let ek = ErrorKinds::InvalidRequest("obj and method required".to_string());
+                    return Some(json_error_value(
+                        "parse failed for batch element",
+                        id,
+                        &ek,
+                        json!({}),
+                    ));
+                }
+            };
+            let params = item
+                .get("params")
+                .cloned()
+                .unwrap_or(serde_json::Value::Null);
+            let (code, buf) = run_jpc(id, obj, method, params);
+            let r = jsonrpc::buffer_to_result(&buf);
+            if code < 0 {
+                Some(r)
+            } else {
+                Some(json!({ "id": id, "jpc": "1.0", "result": r }))
+            }
+        })
+        .collect();
+    if results.is_empty() {
+        return (0, String::new());
+    }
+    let out = match serde_json::to_string(&results) {
+        Ok(x) => x,
+        Err(err) => format!("{err}"),
+    };
+    (0, out)
+}
+
 /**
 tantivy_jpc is the main entry point into a translation layer from Rust to Go for Tantivy
 this function will
@@ -420,6 +1030,9 @@ this function will
 */
 /// # Safety
 ///
+/// The response is held under the returned handle until free_data releases it.  An error response
+/// is held the same way and returned under its negated handle, so any result below -1 must be
+/// freed too, while -1 itself means nothing is held.
 #[no_mangle]
 pub unsafe extern "C" fn tantivy_jpc(
     msg: *const u8,
@@ -428,120 +1041,73 @@ pub unsafe extern "C" fn tantivy_jpc(
     ret_len: *mut usize,
 ) -> i64 {
     debug!("In tantivy_jpc");
+    let (dmr, response) = respond(msg, len);
+    catch_panic(|| send_to_golang(response.into_bytes(), ret, ret_len, dmr)).unwrap_or(-1)
+}
+
+/// # Safety
+///
+/// tantivy_jpc_metrics returns the per call metrics in the Prometheus text exposition format.
+/// The returned handle must be released with free_data like a tantivy_jpc response.
+#[no_mangle]
+pub unsafe extern "C" fn tantivy_jpc_metrics(ret: &mut *const u8, ret_len: *mut usize) -> i64 {
+    match catch_panic(metrics::render) {
+        Ok(text) => {
+            catch_panic(|| send_to_golang(text.into_bytes(), ret, ret_len, 0)).unwrap_or(-1)
+        }
+        Err(_) => -1,
+    }
+}
+
+/// # Safety
+///
+/// tantivy_jpc_buf is tantivy_jpc writing the response into `out`, a caller owned buffer of
+/// `out_cap` bytes, so nothing is left to free.  `out_len` receives the size of the response and
+/// `status` the status of the call, 0 or -1 for an error response.  When it fits the return is
+/// that status.  When it does not fit the request has still run and its response is held under
+/// the returned positive handle, to be collected with read_data into a buffer of at least
+/// `out_len` bytes or dropped with free_data, while `status` still tells whether the call failed.
+#[no_mangle]
+pub unsafe extern "C" fn tantivy_jpc_buf(
+    msg: *const u8,
+    len: usize,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+    status: *mut i64,
+) -> i64 {
+    debug!("In tantivy_jpc_buf");
+    let (dmr, response) = respond(msg, len);
+    catch_panic(|| {
+        let bytes = response.into_bytes();
+        *out_len = bytes.len();
+        *status = dmr;
+        if bytes.len() > out_cap {
+            debug!("response of {} does not fit in {out_cap}", bytes.len());
+            return park_data(&mut lock_recover(&DATA_MAP, "DATA_MAP"), bytes);
+        }
+        copy_out(&bytes, out);
+        dmr
+    })
+    .unwrap_or(-1)
+}
+
+// respond runs the request held in msg, returning the status code and response for it
+unsafe fn respond(msg: *const u8, len: usize) -> (i64, String) {
     let input_string = match str::from_utf8(std::slice::from_raw_parts(msg, len)) {
         Ok(x) => x,
         Err(err) => {
             error!("failed error = {err}");
-            return send_to_golang(err.to_string().as_bytes().to_vec(), ret, ret_len, -1);
-        }
-    };
-    let json_params: Request = match serde_json::from_str(input_string) {
-        Ok(m) => m,
-        Err(_err) => {
-            let r = make_json_error("parse failed for http", "ID not found");
-            return send_to_golang(r.as_bytes().to_vec(), ret, ret_len, -1);
-        }
-    };
-    let mut tm = match TANTIVY_MAP.lock() {
-        Ok(t) => t,
-        Err(e) => {
-            info!("TANTIVY_MAP lock failed {e}");
-            return -1;
-        }
-    };
-    let entity: &mut TantivySession = match json_params.obj {
-        "document" | "builder" | "index" | "indexwriter" | "query_parser" | "searcher"
-        | "index_reader" | "fuzzy_searcher" | "schema" => {
-            let cur_session = tm.get_mut(json_params.id);
-            match cur_session {
-                Some(x) => x,
-                None => {
-                    let stop_words = vec![
-                        "a".to_string(),
-                        "an".to_string(),
-                        "and".to_string(),
-                        "are".to_string(),
-                        "as".to_string(),
-                        "at".to_string(),
-                        "be".to_string(),
-                        "but".to_string(),
-                        "by".to_string(),
-                        "for".to_string(),
-                        "if".to_string(),
-                        "in".to_string(),
-                        "into".to_string(),
-                        "is".to_string(),
-                        "it".to_string(),
-                        "no".to_string(),
-                        "not".to_string(),
-                        "of".to_string(),
-                        "on".to_string(),
-                        "or".to_string(),
-                        "such".to_string(),
-                        "that".to_string(),
-                        "the".to_string(),
-                        "their".to_string(),
-                        "then".to_string(),
-                        "there".to_string(),
-                        "these".to_string(),
-                        "they".to_string(),
-                        "this".to_string(),
-                        "to".to_string(),
-                        "was".to_string(),
-                        "will".to_string(),
-                        "with".to_string(),
-                    ];
-                    let stops = match StopWordFilter::new(Language::English) {
-                        Some(swf) => swf,
-                        None => StopWordFilter::remove(stop_words),
-                    };
-                    let te = TantivySession::new(json_params.id);
-                    tm.insert(json_params.id.to_owned(), te);
-                    let tokenizer_manager = TokenizerManager::default();
-                    tokenizer_manager.register(
-                        "en_stem_with_stop_words",
-                        TextAnalyzer::builder(SimpleTokenizer)
-                            .filter(RemoveLongFilter::limit(40))
-                            .filter(LowerCaser)
-                            .filter(stops.clone())
-                            .filter(Stemmer::new(Language::English))
-                            .build(),
-                    );
-                    tokenizer_manager.register(
-                        "filename",
-                        TextAnalyzer::builder(CamelCaseDigitTokenizer)
-                            .filter(RemoveLongFilter::limit(40))
-                            .filter(LowerCaser)
-                            .filter(stops.clone())
-                            .filter(Stemmer::new(Language::English))
-                            .build(),
-                    );
-                    match tm.get_mut(json_params.id) {
-                        Some(s) => {
-                            s.tokenizer_manager = Some(tokenizer_manager);
-                            s
-                        }
-                        None => {
-                            let msg = ErrorKinds::NotExist(format!(
-                                "Session {} not found",
-                                json_params.id
-                            ))
-                            .to_string();
-                            return send_to_golang(msg.as_bytes().to_vec(), ret, ret_len, -1);
-                        }
-                    } //should be ok just put in
-                }
-            }
-        }
-        _ => {
-            let msg = make_json_error(
-                &ErrorKinds::UnRecognizedCommand(json_params.method.to_string()).to_string(),
-                "noid",
-            );
-
-            return send_to_golang(msg.as_bytes().to_vec(), ret, ret_len, -1);
+            let ek = ErrorKinds::Utf8Error(err);
+            let msg = make_json_error_kind(&ek.to_string(), "ID not found", &ek, json!({}));
+            return (-1, msg);
         }
     };
-    let dmr = entity.do_method(json_params.method, json_params.obj, json_params.params);
-    send_to_golang(entity.return_buffer.as_bytes().to_vec(), ret, ret_len, dmr)
+    catch_panic(|| process_request(input_string)).unwrap_or_else(|ek| {
+        let msg = format!("tantivy_jpc error={ek}");
+        (
+            -1,
+            make_json_error_kind(&msg, "ID not found", &ek, json!({})),
+        )
+    })
 }
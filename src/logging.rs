@@ -0,0 +1,104 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::{Once, RwLock};
+
+/// LogCallback receives every log record that passes the level filter.  `level` runs from 1
+/// (error) to 5 (trace), `target` and `message` are UTF-8 and only valid for the duration of the
+/// call.
+pub type LogCallback = Option<
+    unsafe extern "C" fn(
+        level: i32,
+        target: *const u8,
+        target_len: usize,
+        message: *const u8,
+        message_len: usize,
+    ),
+>;
+
+static CALLBACK: RwLock<LogCallback> = RwLock::new(None);
+static INSTALL: Once = Once::new();
+
+// ForwardLogger hands records to the registered callback, falling back to env_logger on stderr
+// while none is registered
+struct ForwardLogger {
+    fallback: env_logger::Logger,
+}
+
+fn callback() -> LogCallback {
+    match CALLBACK.read() {
+        Ok(cb) => *cb,
+        Err(e) => *e.into_inner(),
+    }
+}
+
+impl Log for ForwardLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match callback() {
+            Some(_) => metadata.level() <= log::max_level(),
+            None => self.fallback.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let cb = match callback() {
+            Some(cb) => cb,
+            None => return self.fallback.log(record),
+        };
+        if record.level() > log::max_level() {
+            return;
+        }
+        let target = record.target();
+        let message = record.args().to_string();
+        unsafe {
+            cb(
+                record.level() as i32,
+                target.as_ptr(),
+                target.len(),
+                message.as_ptr(),
+                message.len(),
+            );
+        }
+    }
+
+    fn flush(&self) {
+        self.fallback.flush();
+    }
+}
+
+/// install makes the forwarding logger the global logger, with env_logger configured from
+/// RUST_LOG or `default_filter` as the fallback.  Only the first call has any effect.
+pub fn install(default_filter: &str) {
+    INSTALL.call_once(|| {
+        let fallback = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or(default_filter),
+        )
+        .build();
+        let max_level = fallback.filter();
+        if log::set_boxed_logger(Box::new(ForwardLogger { fallback })).is_ok() {
+            log::set_max_level(max_level);
+        }
+    });
+}
+
+/// level_filter maps the level numbers used across the C interface, 0 (off) to 5 (trace)
+pub fn level_filter(level: i32) -> Option<LevelFilter> {
+    match level {
+        0 => Some(LevelFilter::Off),
+        1 => Some(LevelFilter::Error),
+        2 => Some(LevelFilter::Warn),
+        3 => Some(LevelFilter::Info),
+        4 => Some(LevelFilter::Debug),
+        5 => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// set_callback registers cb to receive log records at or above level, a None callback sends
+/// logs back to stderr
+pub fn set_callback(cb: LogCallback, level: LevelFilter) {
+    install("info");
+    match CALLBACK.write() {
+        Ok(mut c) => *c = cb,
+        Err(e) => *e.into_inner() = cb,
+    }
+    log::set_max_level(level);
+}
@@ -0,0 +1,162 @@
+use crate::lock_recover;
+use crate::ErrorKinds;
+
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const BYTES_BUCKETS: &[f64] = &[
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+];
+
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, v: f64) {
+        if let Some(i) = self.bounds.iter().position(|b| v <= *b) {
+            self.counts[i] += 1;
+        }
+        self.sum += v;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (b, c) in self.bounds.iter().zip(&self.counts) {
+            cumulative += c;
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{b}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+struct CallStats {
+    calls: u64,
+    latency: Histogram,
+    response_bytes: Histogram,
+}
+
+#[derive(Default)]
+struct Metrics {
+    calls: BTreeMap<(&'static str, &'static str), CallStats>,
+    errors: BTreeMap<(&'static str, &'static str, &'static str), u64>,
+}
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::default());
+}
+
+// labels resolves obj and method to the names in the capabilities table, anything else is
+// counted as unknown so malformed requests cannot grow the label set
+fn labels(obj: &str, method: &str) -> (&'static str, &'static str) {
+    crate::tsession_system::CAPABILITIES
+        .iter()
+        .find(|c| c.obj == obj && c.method == method)
+        .map_or(("unknown", "unknown"), |c| (c.obj, c.method))
+}
+
+fn call_stats(m: &mut Metrics, key: (&'static str, &'static str)) -> &mut CallStats {
+    m.calls.entry(key).or_insert_with(|| CallStats {
+        calls: 0,
+        latency: Histogram::new(LATENCY_BUCKETS),
+        response_bytes: Histogram::new(BYTES_BUCKETS),
+    })
+}
+
+/// record counts a call to obj.method that took elapsed and failed with err, if it failed
+pub fn record(obj: &str, method: &str, elapsed: Duration, err: Option<&ErrorKinds>) {
+    let key = labels(obj, method);
+    let mut guard = lock_recover(&METRICS, "METRICS");
+    let m = &mut *guard;
+    let stats = call_stats(m, key);
+    stats.calls += 1;
+    stats.latency.observe(elapsed.as_secs_f64());
+    if let Some(ek) = err {
+        *m.errors.entry((key.0, key.1, ek.kind())).or_insert(0) += 1;
+    }
+}
+
+/// record_response records the size of the successful response to obj.method handed back to the
+/// caller, once it has been rendered
+pub fn record_response(obj: &str, method: &str, response_len: usize) {
+    let mut m = lock_recover(&METRICS, "METRICS");
+    call_stats(&mut m, labels(obj, method))
+        .response_bytes
+        .observe(response_len as f64);
+}
+
+/// json_len is the size of v serialized compactly, the way responses are sent
+pub fn json_len(v: &serde_json::Value) -> usize {
+    struct Counter(usize);
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut c = Counter(0);
+    let _ = serde_json::to_writer(&mut c, v);
+    c.0
+}
+
+/// render returns every metric in the Prometheus text exposition format
+pub fn render() -> String {
+    let m = lock_recover(&METRICS, "METRICS");
+    let mut out = String::new();
+    out.push_str("# HELP tantivy_jpc_calls_total Calls handled per obj and method.\n");
+    out.push_str("# TYPE tantivy_jpc_calls_total counter\n");
+    for ((obj, method), s) in &m.calls {
+        let _ = writeln!(
+            out,
+            "tantivy_jpc_calls_total{{obj=\"{obj}\",method=\"{method}\"}} {}",
+            s.calls
+        );
+    }
+    out.push_str("# HELP tantivy_jpc_errors_total Failed calls per obj, method and error kind.\n");
+    out.push_str("# TYPE tantivy_jpc_errors_total counter\n");
+    for ((obj, method, kind), n) in &m.errors {
+        let _ = writeln!(
+            out,
+            "tantivy_jpc_errors_total{{obj=\"{obj}\",method=\"{method}\",kind=\"{kind}\"}} {n}"
+        );
+    }
+    out.push_str("# HELP tantivy_jpc_call_duration_seconds Time spent handling calls.\n");
+    out.push_str("# TYPE tantivy_jpc_call_duration_seconds histogram\n");
+    for ((obj, method), s) in &m.calls {
+        let labels = format!("obj=\"{obj}\",method=\"{method}\"");
+        s.latency
+            .write(&mut out, "tantivy_jpc_call_duration_seconds", &labels);
+    }
+    out.push_str("# HELP tantivy_jpc_response_bytes Size of successful responses.\n");
+    out.push_str("# TYPE tantivy_jpc_response_bytes histogram\n");
+    for ((obj, method), s) in &m.calls {
+        let labels = format!("obj=\"{obj}\",method=\"{method}\"");
+        s.response_bytes
+            .write(&mut out, "tantivy_jpc_response_bytes", &labels);
+    }
+    out
+}
@@ -0,0 +1,68 @@
+use crate::process_request;
+use crate::{debug, error, info};
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+/// serve answers newline delimited request envelopes read from input, writing one response line
+/// per request to output, none for a JSON-RPC 2.0 notification.  Requests go through the same
+/// dispatch as tantivy_jpc, so any envelope or batch accepted there is accepted here.  Returns
+/// once input is exhausted.
+pub fn serve<R: BufRead, W: Write>(input: R, mut output: W) -> std::io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        let request = line.trim();
+        if request.is_empty() {
+            continue;
+        }
+        let (code, response) = process_request(request);
+        debug!("served request code={code}");
+        if response.is_empty() {
+            continue;
+        }
+        // responses are compact JSON, handlers that append a newline must not split the line
+        writeln!(output, "{}", response.trim_end())?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// serve_unix listens on a Unix domain socket at path and serves each connection on its own
+/// thread.  A stale socket left at path by an earlier run is replaced, any other file is an error.
+pub fn serve_unix(path: &Path) -> std::io::Result<()> {
+    if let Ok(md) = std::fs::symlink_metadata(path) {
+        if !md.file_type().is_socket() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("listening on {}", path.display());
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                error!("accept failed {e}");
+                continue;
+            }
+        };
+        std::thread::spawn(move || {
+            let reader = match stream.try_clone() {
+                Ok(r) => BufReader::new(r),
+                Err(e) => {
+                    error!("connection setup failed {e}");
+                    return;
+                }
+            };
+            if let Err(e) = serve(reader, stream) {
+                info!("connection closed {e}");
+            }
+        });
+    }
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use crate::debug;
 use crate::make_internal_json_error;
+use crate::tsession_system;
 use crate::ErrorKinds;
 use crate::InternalCallResult;
 use crate::TantivySession;
@@ -8,17 +9,29 @@ extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
 use serde_json::json;
+use std::collections::HashSet;
 use tantivy::schema::{
-    IndexRecordOption, JsonObjectOptions, NumericOptions, Schema, TextFieldIndexing, TextOptions,
+    BytesOptions, FacetOptions, FieldEntry, FieldType, IndexRecordOption, IpAddrOptions,
+    JsonObjectOptions, NumericOptions, Schema, SchemaBuilder, TextFieldIndexing, TextOptions,
     STORED, STRING, TEXT,
 };
-use tantivy::DateOptions;
+use tantivy::{DateOptions, DatePrecision};
 
 macro_rules! impl_simple_type {
     () => {};
     ($self:ident, $handler_params:ident, $handler_obj:ident, $handler_func:ident, $default_type:ident) => {
+        impl_simple_type!(
+            $self,
+            $handler_params,
+            $handler_obj,
+            $handler_func,
+            $default_type,
+            no_extra_options
+        );
+    };
+    ($self:ident, $handler_params:ident, $handler_obj:ident, $handler_func:ident, $default_type:ident, $extra_options:expr) => {
         let field_params =
-            Self::extract_field_params($handler_params)?;
+            Self::extract_field_params($handler_params, stringify!($handler_func))?;
         let mut ni: $default_type;
         if field_params.stored {
             ni = $default_type::default().set_stored();
@@ -31,11 +44,77 @@ macro_rules! impl_simple_type {
         if field_params.fast {
             ni = ni.set_fast();
         }
+        let ni = $extra_options(ni, &field_params)?;
         let f = $handler_obj.$handler_func(&field_params.name, ni);
         $self.return_buffer = json!({ "field": f }).to_string();
     };
 }
 
+fn no_extra_options<T>(o: T, _: &ParamData) -> InternalCallResult<T> {
+    Ok(o)
+}
+
+// numeric_options applies the options only numeric and bool fields take
+fn numeric_options(mut ni: NumericOptions, p: &ParamData) -> InternalCallResult<NumericOptions> {
+    if p.fieldnorms == Some(true) {
+        ni = ni.set_fieldnorm();
+    }
+    if p.coerce {
+        ni = ni.set_coerce();
+    }
+    Ok(ni)
+}
+
+// date_options applies the options only date fields take
+fn date_options(mut di: DateOptions, p: &ParamData) -> InternalCallResult<DateOptions> {
+    if p.fieldnorms == Some(true) {
+        di = di.set_fieldnorm();
+    }
+    if let Some(precision) = p.precision {
+        di = di.set_precision(precision);
+    }
+    Ok(di)
+}
+
+// json_indexing is the text indexing applied to every string found in an indexed json field
+fn json_indexing(p: &ParamData) -> TextFieldIndexing {
+    let record = p.record.unwrap_or(if p.basic {
+        IndexRecordOption::Basic
+    } else {
+        IndexRecordOption::WithFreqsAndPositions
+    });
+    let mut indexing = TextFieldIndexing::default()
+        .set_tokenizer(&p.tokenizer)
+        .set_index_option(record);
+    if let Some(fieldnorms) = p.fieldnorms {
+        indexing = indexing.set_fieldnorms(fieldnorms);
+    }
+    indexing
+}
+
+// get_option reads an optional parameter in the form tantivy serializes it
+fn get_option<T: serde::de::DeserializeOwned>(
+    m: &serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    expected: &str,
+) -> InternalCallResult<Option<T>> {
+    match m.get(key) {
+        Some(v) => match serde_json::from_value(v.clone()) {
+            Ok(o) => Ok(Some(o)),
+            Err(_) => make_internal_json_error(ErrorKinds::BadParams(format!(
+                "{key} must be {expected}, got {v}"
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+// options the linked tantivy revision cannot honor, with the reason reported when one is passed
+const UNSUPPORTED_OPTIONS: &[(&str, &str)] = &[(
+    "fast_tokenizer",
+    "TextOptions::set_fast takes no tokenizer in the linked tantivy revision",
+)];
+
 //let (name, field_type, stored, _indexed, fast) =
 #[derive(Clone)]
 pub struct ParamData {
@@ -46,10 +125,20 @@ pub struct ParamData {
     pub fast: bool,
     pub tokenizer: String,
     pub basic: bool,
+    pub record: Option<IndexRecordOption>,
+    pub fieldnorms: Option<bool>,
+    pub precision: Option<DatePrecision>,
+    pub coerce: bool,
+    pub expand_dots: bool,
 }
 
 impl TantivySession {
-    pub fn extract_field_params(params: serde_json::Value) -> InternalCallResult<ParamData> {
+    // extract_field_params reads the options of a builder.<method> call, rejecting any key that
+    // is not listed for the method in the capabilities table
+    pub fn extract_field_params(
+        params: serde_json::Value,
+        method: &str,
+    ) -> InternalCallResult<ParamData> {
         let m = match params.as_object() {
             Some(x) => x,
             None => {
@@ -58,6 +147,22 @@ impl TantivySession {
                 ))
             }
         };
+        let allowed = tsession_system::method_params("builder", method);
+        // older clients repeat the envelope id in params
+        if let Some(k) = m
+            .keys()
+            .find(|k| *k != "id" && !allowed.contains(&k.as_str()))
+        {
+            if let Some((_, why)) = UNSUPPORTED_OPTIONS.iter().find(|(o, _)| *o == k.as_str()) {
+                return make_internal_json_error(ErrorKinds::BadParams(format!(
+                    "{k} is not supported, {why}"
+                )));
+            }
+            return make_internal_json_error(ErrorKinds::BadParams(format!(
+                "unknown option {k} for {method}, expected one of {}",
+                allowed.join(", ")
+            )));
+        }
         let name = match m.get("name") {
             Some(x) => x
                 .as_str()
@@ -129,6 +234,15 @@ impl TantivySession {
             },
             None => false,
         };
+        let record = get_option(m, "record", "one of basic, freq or position")?;
+        let fieldnorms = get_option(m, "fieldnorms", "true or false")?;
+        let precision = get_option(
+            m,
+            "precision",
+            "one of seconds, milliseconds or microseconds",
+        )?;
+        let coerce = get_option(m, "coerce", "true or false")?.unwrap_or(false);
+        let expand_dots = get_option(m, "expand_dots", "true or false")?.unwrap_or(false);
 
         Ok(ParamData {
             name: name.to_string(),
@@ -138,8 +252,60 @@ impl TantivySession {
             fast,
             tokenizer: tok.to_string(),
             basic,
+            record,
+            fieldnorms,
+            precision,
+            coerce,
+            expand_dots,
         })
     }
+    // schema_from_json builds a schema from a list of field entries in the form tantivy serializes
+    // them, either on its own or as the `schema` member returned by build.  Field names must be
+    // unique and every tokenizer named must be registered with the session.
+    fn schema_from_json(&self, params: serde_json::Value) -> InternalCallResult<Schema> {
+        let desc = match params.get("schema") {
+            Some(s) => s.clone(),
+            None => params,
+        };
+        let entries: Vec<FieldEntry> = serde_json::from_value(desc)
+            .map_err(|e| ErrorKinds::BadParams(format!("invalid schema description {e}")))?;
+        if entries.is_empty() {
+            return make_internal_json_error(ErrorKinds::BadParams(
+                "schema description has no fields".to_string(),
+            ));
+        }
+        let mut names = HashSet::new();
+        let mut sb = SchemaBuilder::default();
+        for entry in entries {
+            if !names.insert(entry.name().to_string()) {
+                return make_internal_json_error(ErrorKinds::BadParams(format!(
+                    "field {} defined more than once",
+                    entry.name()
+                )));
+            }
+            let indexing = match entry.field_type() {
+                FieldType::Str(o) => o.get_indexing_options(),
+                FieldType::JsonObject(o) => o.get_text_indexing_options(),
+                _ => None,
+            };
+            if let Some(i) = indexing {
+                let known = self
+                    .tokenizer_manager
+                    .as_ref()
+                    .is_some_and(|tm| tm.get(i.tokenizer()).is_some());
+                if !known {
+                    return make_internal_json_error(ErrorKinds::BadParams(format!(
+                        "field {} uses unknown tokenizer {}",
+                        entry.name(),
+                        i.tokenizer()
+                    )));
+                }
+            }
+            sb.add_field(entry);
+        }
+        Ok(sb.build())
+    }
+
     pub fn handler_builder(
         &mut self,
         method: &str,
@@ -157,7 +323,7 @@ impl TantivySession {
         };
         match method {
             "add_text_field" => {
-                let field_params = Self::extract_field_params(params)?;
+                let field_params = Self::extract_field_params(params, method)?;
                 let mut ti: TextOptions;
                 match field_params.field_type {
                     1 => {
@@ -177,18 +343,24 @@ impl TantivySession {
                 if field_params.stored {
                     ti = ti | STORED;
                 }
+                let mut indexing = ti.get_indexing_options().cloned().unwrap_or_default();
                 if field_params.field_type != 1 {
                     let options = if field_params.basic {
                         IndexRecordOption::Basic
                     } else {
                         IndexRecordOption::WithFreqsAndPositions
                     };
-                    ti = ti.set_indexing_options(
-                        TextFieldIndexing::default()
-                            .set_tokenizer(&field_params.tokenizer)
-                            .set_index_option(options),
-                    );
+                    indexing = indexing
+                        .set_tokenizer(&field_params.tokenizer)
+                        .set_index_option(options);
+                }
+                if let Some(record) = field_params.record {
+                    indexing = indexing.set_index_option(record);
                 }
+                if let Some(fieldnorms) = field_params.fieldnorms {
+                    indexing = indexing.set_fieldnorms(fieldnorms);
+                }
+                ti = ti.set_indexing_options(indexing);
                 if field_params.fast {
                     ti = ti.set_fast();
                 }
@@ -201,7 +373,10 @@ impl TantivySession {
                 self.return_buffer = json!({ "field": f }).to_string();
             }
             "add_json_field" => {
-                let field_params = Self::extract_field_params(params)?;
+                // json fields were indexed before indexed was honored and the go client never
+                // sends it, so only an explicit false leaves one out of the index
+                let indexed = params.get("indexed").is_none();
+                let field_params = Self::extract_field_params(params, method)?;
                 let mut fi = JsonObjectOptions::default();
                 debug!(
                     "add_json_field: name = {}, field_type = {} stored = {}",
@@ -210,21 +385,92 @@ impl TantivySession {
                 if field_params.stored {
                     fi = fi | STORED;
                 }
+                if indexed || field_params.indexed {
+                    fi = fi.set_indexing_options(json_indexing(&field_params));
+                }
+                if field_params.fast {
+                    fi = fi.set_fast();
+                }
+                // lets `attrs.color` address {"attrs": {"color": ...}} as well as a literal
+                // "attrs.color" key
+                if field_params.expand_dots {
+                    fi = fi.set_expand_dots_enabled();
+                }
 
                 let f = sb.add_json_field(&field_params.name, fi);
                 self.return_buffer = json!({ "field": f }).to_string();
             }
             "add_date_field" => {
-                impl_simple_type!(self, params, sb, add_date_field, DateOptions);
+                impl_simple_type!(self, params, sb, add_date_field, DateOptions, date_options);
             }
             "add_u64_field" => {
-                impl_simple_type!(self, params, sb, add_u64_field, NumericOptions);
+                impl_simple_type!(
+                    self,
+                    params,
+                    sb,
+                    add_u64_field,
+                    NumericOptions,
+                    numeric_options
+                );
             }
             "add_i64_field" => {
-                impl_simple_type!(self, params, sb, add_i64_field, NumericOptions);
+                impl_simple_type!(
+                    self,
+                    params,
+                    sb,
+                    add_i64_field,
+                    NumericOptions,
+                    numeric_options
+                );
             }
             "add_f64_field" => {
-                impl_simple_type!(self, params, sb, add_f64_field, NumericOptions);
+                impl_simple_type!(
+                    self,
+                    params,
+                    sb,
+                    add_f64_field,
+                    NumericOptions,
+                    numeric_options
+                );
+            }
+            "add_bool_field" => {
+                impl_simple_type!(
+                    self,
+                    params,
+                    sb,
+                    add_bool_field,
+                    NumericOptions,
+                    numeric_options
+                );
+            }
+            "add_bytes_field" => {
+                impl_simple_type!(self, params, sb, add_bytes_field, BytesOptions);
+            }
+            "add_ip_addr_field" => {
+                impl_simple_type!(self, params, sb, add_ip_addr_field, IpAddrOptions);
+            }
+            "add_facet_field" => {
+                let field_params = Self::extract_field_params(params, method)?;
+                // facets are always indexed and have no fast field of their own
+                if field_params.fast {
+                    return make_internal_json_error(ErrorKinds::BadParams(format!(
+                        "facet field {} cannot be fast",
+                        field_params.name
+                    )));
+                }
+                let mut fi = FacetOptions::default();
+                if field_params.stored {
+                    fi = fi.set_stored();
+                }
+                let f = sb.add_facet_field(&field_params.name, fi);
+                self.return_buffer = json!({ "field": f }).to_string();
+            }
+            "from_json" => {
+                let schema = self.schema_from_json(params)?;
+                self.builder = None;
+                self.return_buffer = json!({ "schema": schema }).to_string();
+                debug!("{}", self.return_buffer);
+                self.schema = Some(schema)
             }
             "build" => {
                 let sb = match self.builder.take() {
@@ -241,7 +487,7 @@ impl TantivySession {
                 self.schema = Some(schema)
             }
             &_ => {
-                let e = ErrorKinds::BadParams(format!("Unknown method {method}"));
+                let e = ErrorKinds::UnRecognizedCommand(format!("builder.{method}"));
                 self.make_json_error(&e.to_string());
                 return Err(e);
             }
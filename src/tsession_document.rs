@@ -10,41 +10,334 @@ extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
 
+use base64::engine::general_purpose;
+use base64::Engine;
 use serde_json::json;
-use tantivy::schema::Field;
-use tantivy::Document;
+use std::net::IpAddr;
+use tantivy::schema::{Facet, Field, FieldType, FieldValue, Schema, Value};
+use tantivy::tokenizer::PreTokenizedString;
+use tantivy::{DateTime, Document};
 
-fn string_val(v: serde_json::Value) -> tantivy::schema::Value {
-    tantivy::schema::Value::Str(v.as_str().unwrap_or("empty").to_string())
+// Params are the request params, setters that need more than the value read them from here
+type Params = serde_json::Map<String, serde_json::Value>;
+
+// lenient reports whether the request asked for values of the wrong JSON type to be coerced
+// rather than rejected, handle_add_field has already rejected a lenient that is not a bool
+fn lenient(params: &Params) -> bool {
+    params
+        .get("lenient")
+        .and_then(|l| l.as_bool())
+        .unwrap_or(false)
+}
+
+fn coerce_error(v: &serde_json::Value, kind: &str) -> InternalCallResult<Value> {
+    make_internal_json_error(ErrorKinds::BadParams(format!(
+        "Unable to coerce {v} as {kind}"
+    )))
+}
+
+// parse_str reads a number given as text, which is only accepted in lenient mode
+fn parse_str<T: std::str::FromStr>(v: &serde_json::Value) -> Option<T> {
+    v.as_str().and_then(|s| s.trim().parse().ok())
+}
+
+// whole_f64 accepts a float without a fractional part that fits in [min, max)
+fn whole_f64(v: &serde_json::Value, min: f64, max: f64) -> Option<f64> {
+    v.as_f64()
+        .filter(|f| f.fract() == 0.0 && *f >= min && *f < max)
+}
+
+fn string_val(v: serde_json::Value, params: &Params) -> InternalCallResult<Value> {
+    if let Some(s) = v.as_str() {
+        return Ok(Value::Str(s.to_string()));
+    }
+    if lenient(params) && (v.is_number() || v.is_boolean()) {
+        return Ok(Value::Str(v.to_string()));
+    }
+    coerce_error(&v, "text")
+}
+
+fn json_val(v: serde_json::Value, params: &Params) -> InternalCallResult<Value> {
+    if let Some(o) = v.as_object() {
+        return Ok(Value::JsonObject(o.clone()));
+    }
+    if lenient(params) {
+        if let Some(serde_json::Value::Object(o)) =
+            v.as_str().and_then(|s| serde_json::from_str(s).ok())
+        {
+            return Ok(Value::JsonObject(o));
+        }
+    }
+    coerce_error(&v, "json object")
+}
+
+fn int_val(v: serde_json::Value, params: &Params) -> InternalCallResult<Value> {
+    if let Some(i) = v.as_i64() {
+        return Ok(Value::I64(i));
+    }
+    if lenient(params) {
+        let i = parse_str(&v)
+            .or_else(|| whole_f64(&v, i64::MIN as f64, i64::MAX as f64).map(|f| f as i64));
+        if let Some(i) = i {
+            return Ok(Value::I64(i));
+        }
+    }
+    coerce_error(&v, "i64")
+}
+
+fn uint_val(v: serde_json::Value, params: &Params) -> InternalCallResult<Value> {
+    if let Some(u) = v.as_u64() {
+        return Ok(Value::U64(u));
+    }
+    if lenient(params) {
+        let u = parse_str(&v).or_else(|| whole_f64(&v, 0.0, u64::MAX as f64).map(|f| f as u64));
+        if let Some(u) = u {
+            return Ok(Value::U64(u));
+        }
+    }
+    coerce_error(&v, "u64")
+}
+
+fn f64_val(v: serde_json::Value, params: &Params) -> InternalCallResult<Value> {
+    if let Some(f) = v.as_f64() {
+        return Ok(Value::F64(f));
+    }
+    match parse_str::<f64>(&v) {
+        Some(f) if lenient(params) && f.is_finite() => Ok(Value::F64(f)),
+        _ => coerce_error(&v, "f64"),
+    }
+}
+
+// dates are RFC3339 text or a unix timestamp in the unit named by the precision param, seconds
+// when it is not given
+fn date_val(v: serde_json::Value, params: &Params) -> InternalCallResult<Value> {
+    if let Some(s) = v.as_str() {
+        let dt = chrono::DateTime::parse_from_rfc3339(s)?;
+        return Ok(Value::Date(DateTime::from_timestamp_micros(
+            dt.timestamp_micros(),
+        )));
+    }
+    let ts = match v.as_i64() {
+        Some(ts) => ts,
+        None => {
+            return make_internal_json_error(ErrorKinds::BadParams(format!(
+                "Unable to coerce {v} as date, expected RFC3339 text or a unix timestamp"
+            )))
+        }
+    };
+    let precision = params.get("precision").and_then(|p| p.as_str());
+    let dt = match precision {
+        None | Some("seconds") => DateTime::from_timestamp_secs(ts),
+        Some("milliseconds") => DateTime::from_timestamp_millis(ts),
+        Some("microseconds") => DateTime::from_timestamp_micros(ts),
+        Some(p) => {
+            return make_internal_json_error(ErrorKinds::BadParams(format!(
+                "precision must be seconds, milliseconds or microseconds, got {p}"
+            )))
+        }
+    };
+    Ok(Value::Date(dt))
+}
+
+fn pretokenized_val(v: serde_json::Value, _: &Params) -> InternalCallResult<Value> {
+    match serde_json::from_value::<PreTokenizedString>(v) {
+        Ok(p) => Ok(Value::PreTokStr(p)),
+        Err(e) => make_internal_json_error(ErrorKinds::BadParams(format!(
+            "value must be a pre-tokenized string with text and tokens, {e}"
+        ))),
+    }
+}
+
+fn bool_val(v: serde_json::Value, params: &Params) -> InternalCallResult<Value> {
+    if let Some(b) = v.as_bool() {
+        return Ok(Value::Bool(b));
+    }
+    match parse_str::<bool>(&v) {
+        Some(b) if lenient(params) => Ok(Value::Bool(b)),
+        _ => coerce_error(&v, "bool"),
+    }
+}
+
+// bytes are passed either base64 encoded or as an array of byte values
+fn bytes_val(v: serde_json::Value, _: &Params) -> InternalCallResult<Value> {
+    if let Some(s) = v.as_str() {
+        return match general_purpose::STANDARD.decode(s) {
+            Ok(b) => Ok(Value::Bytes(b)),
+            Err(e) => make_internal_json_error(ErrorKinds::BadParams(format!(
+                "bytes value is not valid base64 {e}"
+            ))),
+        };
+    }
+    let bytes: Option<Vec<u8>> = v.as_array().and_then(|a| {
+        a.iter()
+            .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+            .collect()
+    });
+    match bytes {
+        Some(b) => Ok(Value::Bytes(b)),
+        None => make_internal_json_error(ErrorKinds::BadParams(format!(
+            "Unable to coerce {v} as bytes"
+        ))),
+    }
+}
+
+// tantivy stores every address as IPv6, IPv4 addresses are mapped
+fn ip_addr_val(v: serde_json::Value, _: &Params) -> InternalCallResult<Value> {
+    match v.as_str().and_then(|s| s.parse::<IpAddr>().ok()) {
+        Some(IpAddr::V4(a)) => Ok(Value::IpAddr(a.to_ipv6_mapped())),
+        Some(IpAddr::V6(a)) => Ok(Value::IpAddr(a)),
+        None => make_internal_json_error(ErrorKinds::BadParams(format!(
+            "Unable to coerce {v} as ip address"
+        ))),
+    }
+}
+
+fn facet_val(v: serde_json::Value, _: &Params) -> InternalCallResult<Value> {
+    let s = match v.as_str() {
+        Some(s) => s,
+        None => {
+            return make_internal_json_error(ErrorKinds::BadParams(format!(
+                "Unable to coerce {v} as facet"
+            )))
+        }
+    };
+    match Facet::from_text(s) {
+        Ok(f) => Ok(Value::Facet(f)),
+        Err(e) => make_internal_json_error(ErrorKinds::BadParams(format!("invalid facet {s} {e}"))),
+    }
 }
 
-fn json_val(v: serde_json::Value) -> tantivy::schema::Value {
-    tantivy::schema::Value::JsonObject(v.as_object().unwrap_or(&serde_json::Map::new()).clone())
+/// resolve_field finds the field named by v, either its name or its numeric id, in schema
+pub(crate) fn resolve_field(schema: &Schema, v: &serde_json::Value) -> InternalCallResult<Field> {
+    if let Some(name) = v.as_str() {
+        return match schema.get_field(name) {
+            Ok(f) => Ok(f),
+            Err(_) => make_internal_json_error(ErrorKinds::BadParams(format!(
+                "field {name} is not in the schema"
+            ))),
+        };
+    }
+    match v.as_u64() {
+        Some(id) if id < schema.fields().count() as u64 => Ok(Field::from_field_id(id as u32)),
+        Some(id) => make_internal_json_error(ErrorKinds::BadParams(format!(
+            "field id {id} is not in the schema"
+        ))),
+        None => make_internal_json_error(ErrorKinds::BadParams(format!(
+            "field must be a field name or id, got {v}"
+        ))),
+    }
+}
+
+/// json_to_doc converts a JSON object keyed by field name into a document of schema, values are
+/// converted to the type of their field and arrays give a field several values
+pub(crate) fn json_to_doc(
+    schema: &Schema,
+    obj: serde_json::Map<String, serde_json::Value>,
+) -> InternalCallResult<Document> {
+    if let Some(name) = obj.keys().find(|k| schema.get_field(k).is_err()) {
+        return make_internal_json_error(ErrorKinds::BadParams(format!(
+            "field {name} is not in the schema"
+        )));
+    }
+    Ok(schema.json_object_to_doc(obj)?)
+}
+
+/// doc_id reads the doc_id param naming a pending document
+pub(crate) fn doc_id(params: &serde_json::Value, key: &str) -> InternalCallResult<usize> {
+    match params.get(key).and_then(|d| d.as_u64()) {
+        Some(id) if id > 0 => Ok(id as usize),
+        _ => make_internal_json_error(ErrorKinds::BadParams(format!(
+            "{key} must name a pending document, got {}",
+            params.get(key).unwrap_or(&serde_json::Value::Null)
+        ))),
+    }
+}
+
+/// pending_error explains why doc_id is not a pending document, handles below next_doc_id were
+/// handed out before and have since been added to the index or discarded
+pub(crate) fn pending_error(doc_id: usize, next_doc_id: usize) -> ErrorKinds {
+    if doc_id > 0 && doc_id < next_doc_id {
+        ErrorKinds::BadParams(format!(
+            "document {doc_id} is no longer pending, it was already added or discarded"
+        ))
+    } else {
+        ErrorKinds::BadParams(format!("document {doc_id} does not exist"))
+    }
 }
 
-fn int_val(v: serde_json::Value) -> tantivy::schema::Value {
-    tantivy::schema::Value::I64(v.as_i64().unwrap_or(0))
+/// pending_response is the response to a call creating a pending document.  document_count
+/// repeats the doc_id for clients written before handles were stable.
+pub(crate) fn pending_response(doc_id: usize) -> String {
+    json!({ "doc_id": doc_id, "document_count": doc_id }).to_string()
 }
 
-fn uint_val(v: serde_json::Value) -> tantivy::schema::Value {
-    tantivy::schema::Value::U64(v.as_u64().unwrap_or(0))
+// type_name is the name a field type is serialized with in the schema
+fn type_name(ft: &FieldType) -> &'static str {
+    match ft {
+        FieldType::Str(_) => "text",
+        FieldType::U64(_) => "u64",
+        FieldType::I64(_) => "i64",
+        FieldType::F64(_) => "f64",
+        FieldType::Bool(_) => "bool",
+        FieldType::Date(_) => "date",
+        FieldType::Facet(_) => "facet",
+        FieldType::Bytes(_) => "bytes",
+        FieldType::JsonObject(_) => "json_object",
+        FieldType::IpAddr(_) => "ip_addr",
+    }
+}
+
+fn value_name(v: &Value) -> &'static str {
+    match v {
+        Value::Str(_) | Value::PreTokStr(_) => "text",
+        Value::U64(_) => "u64",
+        Value::I64(_) => "i64",
+        Value::F64(_) => "f64",
+        Value::Bool(_) => "bool",
+        Value::Date(_) => "date",
+        Value::Facet(_) => "facet",
+        Value::Bytes(_) => "bytes",
+        Value::JsonObject(_) => "json_object",
+        Value::IpAddr(_) => "ip_addr",
+    }
+}
+
+fn value_matches(v: &Value, ft: &FieldType) -> bool {
+    value_name(v) == type_name(ft)
 }
 
 impl TantivySession {
+    // push_document adds d to the pending documents, returning the doc_id that addresses it
+    pub(crate) fn push_document(&mut self, d: Document) -> usize {
+        let id = self.next_doc_id;
+        self.next_doc_id += 1;
+        self.doc.get_or_insert_with(HashMap::new).insert(id, d);
+        id
+    }
+
+    // doc_schema is the schema documents are built against
+    fn doc_schema(&self) -> Result<&Schema, ErrorKinds> {
+        self.schema.as_ref().ok_or_else(|| {
+            ErrorKinds::BadInitialization(
+                "documents need a schema, call builder.build first".to_string(),
+            )
+        })
+    }
+
+    // pending_document looks up a pending document by doc_id, explaining a handle that is not pending
+    fn pending_document(&mut self, doc_id: usize) -> InternalCallResult<&mut Document> {
+        let next = self.next_doc_id;
+        match self.doc.as_mut().and_then(|d| d.get_mut(&doc_id)) {
+            Some(d) => Ok(d),
+            None => Err(pending_error(doc_id, next)),
+        }
+    }
+
     fn handle_add_field(
         &mut self,
         params: serde_json::Value,
-        func: fn(v: serde_json::Value) -> tantivy::schema::Value,
+        func: fn(v: serde_json::Value, params: &Params) -> InternalCallResult<Value>,
     ) -> InternalCallResult<u32> {
-        let doc = self.doc.as_mut();
-        let d = match doc {
-            Some(v) => v,
-            None => {
-                return make_internal_json_error(ErrorKinds::BadInitialization(
-                    "add_text with no doucments created".to_string(),
-                ))
-            }
-        };
         let m = match params.as_object() {
             Some(m) => m,
             None => {
@@ -53,33 +346,10 @@ impl TantivySession {
                 ))
             }
         };
-        let doc_idx = match m.get("doc_id") {
-            Some(d) => match d.as_u64() {
-                Some(u) => (u - 1) as usize,
-                None => {
-                    return Err(ErrorKinds::BadParams(format!(
-                        "Unable to coerce {} as uint",
-                        d
-                    )))
-                }
-            },
-            None => {
-                return Err(ErrorKinds::BadParams(format!(
-                    "Could not find doc_id in {:?}",
-                    m
-                )))
-            }
-        };
-        let field_idx = match m.get("field") {
-            Some(d) => match d.as_u64() {
-                Some(u) => u as u32,
-                None => {
-                    return Err(ErrorKinds::BadParams(format!(
-                        "Unable to coerce {} as uint",
-                        d
-                    )))
-                }
-            },
+        let doc_idx = doc_id(&params, "doc_id")?;
+        let schema = self.doc_schema()?;
+        let f = match m.get("field") {
+            Some(v) => resolve_field(schema, v)?,
             None => {
                 return Err(ErrorKinds::BadParams(format!(
                     "Could not find field in {:?}",
@@ -87,35 +357,118 @@ impl TantivySession {
                 )))
             }
         };
-        let f = Field::from_field_id(field_idx);
         debug!("add_text: name = {:?}", m);
-        match m.get("field") {
-            Some(f) => f.as_i64(),
+        if let Some(l) = m.get("lenient").filter(|l| !l.is_boolean()) {
+            return make_internal_json_error(ErrorKinds::BadParams(format!(
+                "lenient must be true or false, got {l}"
+            )));
+        }
+        let field_val = match m.get("value") {
+            Some(v) => func(v.clone(), m)?,
             None => {
-                return make_internal_json_error(ErrorKinds::BadParams(
-                    "field must contain integer id".to_string(),
+                return make_internal_json_error(ErrorKinds::BadInitialization(
+                    "field text required for document".to_string(),
                 ))
             }
         };
-        let field_val = match m.get("value") {
-            Some(v) => func(v.clone()),
+        let entry = schema.get_field_entry(f);
+        if !value_matches(&field_val, entry.field_type()) {
+            return make_internal_json_error(ErrorKinds::BadParams(format!(
+                "field {} expects {} values, got {}",
+                entry.name(),
+                type_name(entry.field_type()),
+                value_name(&field_val)
+            )));
+        }
+        self.pending_document(doc_idx)?
+            .add_field_value(f, field_val);
+        Ok(0)
+    }
+
+    // list reports the doc_id of every pending document in creation order
+    fn list_documents(&mut self) -> InternalCallResult<u32> {
+        let mut ids: Vec<usize> = self
+            .doc
+            .as_ref()
+            .map_or_else(Vec::new, |d| d.keys().copied().collect());
+        ids.sort_unstable();
+        self.return_buffer = json!({ "doc_ids": ids, "next_doc_id": self.next_doc_id }).to_string();
+        Ok(0)
+    }
+
+    // inspect renders a pending document as json keyed by field name
+    fn inspect_document(&mut self, params: serde_json::Value) -> InternalCallResult<u32> {
+        let id = doc_id(&params, "doc_id")?;
+        let schema = self.doc_schema()?.clone();
+        let named = schema.to_named_doc(self.pending_document(id)?);
+        self.return_buffer = json!({ "doc_id": id, "document": named }).to_string();
+        Ok(0)
+    }
+
+    // clear_field drops every value a pending document holds for one field so it can be set again
+    fn clear_field(&mut self, params: serde_json::Value) -> InternalCallResult<u32> {
+        let id = doc_id(&params, "doc_id")?;
+        let f = match params.get("field") {
+            Some(v) => resolve_field(self.doc_schema()?, v)?,
             None => {
-                return make_internal_json_error(ErrorKinds::BadInitialization(
-                    "field text required for document".to_string(),
+                return make_internal_json_error(ErrorKinds::BadParams(
+                    "clear_field needs a field".to_string(),
                 ))
             }
         };
-        let cur_doc = match d.get_mut(&doc_idx) {
-            Some(d) => d,
+        let d = self.pending_document(id)?;
+        let before = d.len();
+        let kept: Vec<FieldValue> = std::mem::take(d)
+            .into_iter()
+            .filter(|fv| fv.field() != f)
+            .collect();
+        let removed = before - kept.len();
+        *d = Document::from(kept);
+        self.return_buffer = json!({ "doc_id": id, "removed": removed }).to_string();
+        Ok(0)
+    }
+
+    // discard drops a pending document, its doc_id is not handed out again
+    fn discard_document(&mut self, params: serde_json::Value) -> InternalCallResult<u32> {
+        let id = doc_id(&params, "doc_id")?;
+        match self.doc.as_mut().and_then(|d| d.remove(&id)) {
+            Some(_) => {
+                self.return_buffer = json!({ "discarded": id }).to_string();
+                Ok(0)
+            }
+            None => Err(pending_error(id, self.next_doc_id)),
+        }
+    }
+
+    // add_json_doc converts a JSON object keyed by field name into a document, either kept
+    // pending like one built with create or, with add, handed straight to the index writer
+    fn add_json_doc(&mut self, params: serde_json::Value) -> InternalCallResult<u32> {
+        let schema = self.doc_schema()?;
+        let obj = match params.get("document").and_then(|d| d.as_object()) {
+            Some(o) => o,
             None => {
-                return make_internal_json_error(ErrorKinds::BadInitialization(format!(
-                    "document at index {doc_idx} does not exist"
-                )))
+                return make_internal_json_error(ErrorKinds::BadParams(
+                    "document must be a json object keyed by field name".to_string(),
+                ))
             }
         };
-        cur_doc.add_field_value(f, field_val);
+        let add = match params.get("add") {
+            Some(v) => v
+                .as_bool()
+                .ok_or_else(|| ErrorKinds::BadParams("add must be true or false".to_string()))?,
+            None => false,
+        };
+        let doc = json_to_doc(schema, obj.clone())?;
+        if add {
+            let writer = Self::open_writer(&mut self.indexwriter, &self.index, self.memsize)?;
+            let os = writer.add_document(doc)?;
+            self.return_buffer = json!({ "opstamp": os }).to_string();
+        } else {
+            self.return_buffer = pending_response(self.push_document(doc));
+        }
         Ok(0)
     }
+
     pub fn handle_document(
         &mut self,
         method: &str,
@@ -139,27 +492,48 @@ impl TantivySession {
                 self.handle_add_field(params, uint_val)?;
                 0
             }
+            "add_f64" => {
+                self.handle_add_field(params, f64_val)?;
+                0
+            }
+            "add_date" => {
+                self.handle_add_field(params, date_val)?;
+                0
+            }
+            "add_pretokenized" => {
+                self.handle_add_field(params, pretokenized_val)?;
+                0
+            }
+            "add_bool" => {
+                self.handle_add_field(params, bool_val)?;
+                0
+            }
+            "add_bytes" => {
+                self.handle_add_field(params, bytes_val)?;
+                0
+            }
+            "add_ip_addr" | "add_ip" => {
+                self.handle_add_field(params, ip_addr_val)?;
+                0
+            }
+            "add_facet" => {
+                self.handle_add_field(params, facet_val)?;
+                0
+            }
+            "add_json_doc" => self.add_json_doc(params)?,
+            "list" => self.list_documents()?,
+            "inspect" => self.inspect_document(params)?,
+            "clear_field" => self.clear_field(params)?,
+            "discard" => self.discard_document(params)?,
             "create" => {
-                let doc = self.doc.as_mut();
-                let length: usize;
-                match doc {
-                    Some(x) => {
-                        let l = x.len();
-                        x.insert(l, Document::default());
-                        length = x.len();
-                    }
-                    None => {
-                        let nd = Document::default();
-                        let mut hm = HashMap::<usize, Document>::new();
-                        hm.insert(0, nd);
-                        self.doc = Some(hm);
-                        length = 1;
-                    }
-                };
-                self.return_buffer = json!({ "document_count": length }).to_string();
+                self.return_buffer = pending_response(self.push_document(Document::default()));
                 0
             }
-            &_ => 0,
+            &_ => {
+                return make_internal_json_error(ErrorKinds::UnRecognizedCommand(format!(
+                    "document.{method}"
+                )))
+            }
         };
         Ok(0)
     }
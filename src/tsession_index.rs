@@ -1,4 +1,7 @@
+use crate::ingest;
 use crate::make_internal_json_error;
+use crate::tsession_document::{doc_id, pending_error};
+use crate::tsession_schema::schema_diff;
 use crate::ErrorKinds;
 use crate::InternalCallResult;
 use crate::TantivySession;
@@ -8,6 +11,8 @@ extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
 use serde_json::json;
+use tantivy::directory::error::OpenDirectoryError;
+use tantivy::directory::MmapDirectory;
 use tantivy::schema::FieldType;
 use tantivy::DateTime;
 use tantivy::Term;
@@ -55,6 +60,15 @@ impl TantivySession {
         };
 
         let dir_to_use = this.get("directory").and_then(|x| x.as_str()).unwrap_or("");
+        let mode = this
+            .get("mode")
+            .and_then(|x| x.as_str())
+            .unwrap_or("create_or_open");
+        if !matches!(mode, "create" | "open" | "create_or_open") {
+            return make_internal_json_error(ErrorKinds::BadParams(format!(
+                "mode must be create, open or create_or_open, got {mode}"
+            )));
+        }
 
         self.memsize = this
             .get("memsize")
@@ -62,26 +76,58 @@ impl TantivySession {
             .unwrap_or(DEFAULT_INDEX_WRITER_MEM_SIZE);
 
         if !dir_to_use.is_empty() {
-            let idx = match tantivy::Index::open_in_dir(dir_to_use) {
-                Ok(p) => p,
-                Err(err) => {
-                    info!("error={}\n", err);
-                    tantivy::Index::create_in_dir(
-                        dir_to_use,
-                        if let Some(s) = &self.schema {
-                            s.to_owned()
-                        } else {
-                            return make_internal_json_error(ErrorKinds::BadInitialization(
-                                "A schema must be created before an index".to_string(),
-                            ));
-                        },
-                    )?
+            let dir = match MmapDirectory::open(dir_to_use) {
+                Ok(d) => d,
+                Err(OpenDirectoryError::DoesNotExist(_)) if mode == "open" => {
+                    return make_internal_json_error(ErrorKinds::NotExist(format!(
+                        "no index in {dir_to_use}, the directory does not exist"
+                    )));
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let exists = tantivy::Index::exists(&dir).map_err(|e| ErrorKinds::IO(e.to_string()))?;
+            let idx = if exists {
+                if mode == "create" {
+                    return make_internal_json_error(ErrorKinds::BadParams(format!(
+                        "an index already exists in {dir_to_use}"
+                    )));
+                }
+                let idx = tantivy::Index::open_in_dir(dir_to_use)?;
+                // opening keeps the on-disk schema, a different requested schema is reported
+                // rather than silently replaced
+                if let Some(requested) = &self.schema {
+                    if let Some(diff) = schema_diff(&idx.schema(), requested) {
+                        info!("schema of {dir_to_use} differs from the session schema");
+                        return make_internal_json_error(ErrorKinds::SchemaMismatch(diff));
+                    }
                 }
+                idx
+            } else {
+                if mode == "open" {
+                    return make_internal_json_error(ErrorKinds::NotExist(format!(
+                        "no index in {dir_to_use}"
+                    )));
+                }
+                tantivy::Index::create_in_dir(
+                    dir_to_use,
+                    if let Some(s) = &self.schema {
+                        s.to_owned()
+                    } else {
+                        return make_internal_json_error(ErrorKinds::BadInitialization(
+                            "A schema must be created before an index".to_string(),
+                        ));
+                    },
+                )?
             };
             idx.tokenizers().register("en_stem_with_stop_words", default_tokenizer);
             idx.tokenizers().register("filename", filename_tokenizer);
             Ok(Box::new(idx))
         } else {
+            if mode == "open" {
+                return make_internal_json_error(ErrorKinds::BadParams(
+                    "an in-memory index cannot be opened, a directory is required".to_string(),
+                ));
+            }
             debug!("Creating index in RAM");
             self.index = Some(Box::new(tantivy::Index::create_in_ram(
                 match &self.schema {
@@ -112,25 +158,21 @@ impl TantivySession {
         debug!("Index");
         let idx: &mut Box<tantivy::Index> = match self.index.as_mut() {
             Some(x) => x,
-            None => match self.create_index(params.clone()) {
-                Ok(x) => {
-                    self.index = Some(x);
-                    let r = match self.index.as_mut() {
-                        Some(s) => s,
-                        None => {
-                            return make_internal_json_error(ErrorKinds::Other(
-                                "failed to get Index as reference".to_string(),
-                            ));
-                        }
-                    };
-                    self.schema = Some(r.schema());
-                    r
-                }
-                Err(err) => {
-                    let buf = format!("{err}");
-                    return make_internal_json_error(ErrorKinds::BadParams(buf));
-                }
-            },
+            None => {
+                // create_index errors keep their kind, NotExist and SchemaMismatch in particular
+                let x = self.create_index(params.clone())?;
+                self.index = Some(x);
+                let r = match self.index.as_mut() {
+                    Some(s) => s,
+                    None => {
+                        return make_internal_json_error(ErrorKinds::Other(
+                            "failed to get Index as reference".to_string(),
+                        ));
+                    }
+                };
+                self.schema = Some(r.schema());
+                r
+            }
         };
         match method {
             "reader_builder" => {
@@ -161,49 +203,45 @@ impl TantivySession {
         };
         Ok(0)
     }
+    // open_writer returns the session's index writer, creating it on first use.  It takes the
+    // fields it needs so callers can keep borrowing the rest of the session.
+    pub(crate) fn open_writer<'a>(
+        indexwriter: &'a mut Option<Box<tantivy::IndexWriter>>,
+        index: &Option<Box<tantivy::Index>>,
+        memsize: u64,
+    ) -> InternalCallResult<&'a mut Box<tantivy::IndexWriter>> {
+        if indexwriter.is_none() {
+            let bi = match index.as_ref() {
+                Some(x) => x,
+                None => {
+                    return make_internal_json_error(ErrorKinds::BadInitialization(
+                        "need index created for writer".to_string(),
+                    ))
+                }
+            };
+            *indexwriter = Some(Box::new((*bi).writer(memsize as usize)?));
+        }
+        indexwriter.as_mut().ok_or(ErrorKinds::BadInitialization(
+            "need index created for writer".to_string(),
+        ))
+    }
+
     pub fn handle_index_writer(
         &mut self,
         method: &str,
         params: serde_json::Value,
     ) -> InternalCallResult<u32> {
         debug!("IndexWriter");
-        let writer = match self.indexwriter.as_mut() {
-            Some(x) => x,
-            None => {
-                let bi = match self.index.as_mut().take() {
-                    Some(x) => x,
-                    None => {
-                        return make_internal_json_error(ErrorKinds::BadInitialization(
-                            "need index created for writer".to_string(),
-                        ))
-                    }
-                };
-                let iw = Box::new((*bi).writer(self.memsize as usize)?);
-                self.indexwriter = Some(iw);
-                self.indexwriter
-                    .as_mut()
-                    .ok_or(ErrorKinds::BadInitialization(
-                        "need index created for writer".to_string(),
-                    ))?
-            }
-        };
+        let writer = Self::open_writer(&mut self.indexwriter, &self.index, self.memsize)?;
         match method {
             "add_document" => {
-                let mut doc = self.doc.take();
-                let d = doc.as_mut().ok_or(ErrorKinds::NotExist(
-                    "No value for hash in Documents".to_string(),
-                ))?;
-                let m = params.as_object().ok_or(ErrorKinds::BadParams(
-                    "invalid parameters pass to Document add_text".to_string(),
-                ))?;
-                let doc_idx =
-                    m.get("id").unwrap_or(&json! {0_i32}).as_u64().unwrap_or(0) as usize - 1;
-                let rm = d.remove(&doc_idx).ok_or(ErrorKinds::BadInitialization(
-                    "need index created for writer".to_string(),
-                ))?;
+                let doc_idx = doc_id(&params, "id")?;
+                let rm = match self.doc.as_mut().and_then(|d| d.remove(&doc_idx)) {
+                    Some(d) => d,
+                    None => return Err(pending_error(doc_idx, self.next_doc_id)),
+                };
                 let os = writer.add_document(rm)?;
                 self.return_buffer = json!({ "opstamp": os }).to_string();
-                self.doc = doc;
                 debug!("{}", self.return_buffer);
             }
             "delete_term" => {
@@ -305,6 +343,18 @@ impl TantivySession {
                     self.return_buffer = json!({ "opstamp": ostamp }).to_string();
                 }
             }
+            "ingest_ndjson" => {
+                let schema = match self.schema.as_ref() {
+                    Some(s) => s,
+                    None => {
+                        return make_internal_json_error(ErrorKinds::BadInitialization(
+                            "schema not available during ingest_ndjson".to_string(),
+                        ))
+                    }
+                };
+                let report = ingest::ingest_ndjson(writer, schema, &params)?;
+                self.return_buffer = serde_json::to_string(&report)?;
+            }
             "commit" => {
                 match writer.commit() {
                     Ok(x) => {
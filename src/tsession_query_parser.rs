@@ -143,7 +143,7 @@ impl TantivySession {
             }
             return Ok(0);
         }
-        let e = ErrorKinds::BadParams(format!("Unknown method {method}"));
+        let e = ErrorKinds::UnRecognizedCommand(format!("query_parser.{method}"));
         Err(e)
     }
 }
@@ -0,0 +1,213 @@
+use crate::debug;
+use crate::lock_recover;
+use crate::make_internal_json_error;
+use crate::ErrorKinds;
+use crate::InternalCallResult;
+use crate::TantivySession;
+use crate::TANTIVY_MAP;
+use crate::{error, info};
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Environment variable holding the idle time, in seconds, after which a session is evicted
+pub const SESSION_IDLE_TTL_ENV: &str = "ELV_SESSION_IDLE_TTL";
+
+// idle time in seconds after which sessions are evicted, 0 disables eviction
+static IDLE_TTL_SECS: AtomicU64 = AtomicU64::new(0);
+
+// time of the last idle session sweep in milliseconds since the epoch, sweeps run at most once
+// every half ttl so requests do not scan every session
+static LAST_SWEEP_MS: AtomicU64 = AtomicU64::new(0);
+
+/// set_idle_ttl sets the idle time in seconds after which sessions are evicted, 0 disables eviction
+pub fn set_idle_ttl(secs: u64) {
+    IDLE_TTL_SECS.store(secs, Ordering::Relaxed);
+}
+
+/// idle_ttl returns the idle time after which sessions are evicted, if eviction is enabled
+pub fn idle_ttl() -> Option<Duration> {
+    match IDLE_TTL_SECS.load(Ordering::Relaxed) {
+        0 => None,
+        s => Some(Duration::from_secs(s)),
+    }
+}
+
+impl TantivySession {
+    // close releases everything held by the session.  The writer is shut down through
+    // wait_merging_threads so pending merges finish before the index is dropped, documents added
+    // since the last commit are discarded.
+    pub fn close(&mut self) {
+        debug!("closing session {}", self.id);
+        if let Some(w) = self.indexwriter.take() {
+            info!(
+                "session {} closed with an open writer, uncommitted documents are discarded",
+                self.id
+            );
+            if let Err(e) = (*w).wait_merging_threads() {
+                error!("session {} writer shutdown failed {e}", self.id);
+            }
+        }
+        self.searcher = None;
+        self.index_reader_builder = None;
+        self.query_parser = None;
+        self.dyn_q = None;
+        self.fuzzy_q = None;
+        self.index = None;
+        self.doc = None;
+        self.builder = None;
+    }
+
+    fn session_info(&self) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "idle_secs": self.last_used.elapsed().as_secs(),
+            "components": {
+                "builder": self.builder.is_some(),
+                "schema": self.schema.is_some(),
+                "index": self.index.is_some(),
+                "writer": self.indexwriter.is_some(),
+                "reader": self.index_reader_builder.is_some() || self.searcher.is_some(),
+                "query": self.query_parser.is_some() || self.dyn_q.is_some() || self.fuzzy_q.is_some(),
+            },
+            "pending_documents": self.doc.as_ref().map_or(0, |d| d.len()),
+        })
+    }
+}
+
+fn sessions() -> Vec<(String, Arc<Mutex<TantivySession>>)> {
+    let tm = lock_recover(&TANTIVY_MAP, "TANTIVY_MAP");
+    tm.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+}
+
+fn find_session(id: &str) -> InternalCallResult<Arc<Mutex<TantivySession>>> {
+    let tm = lock_recover(&TANTIVY_MAP, "TANTIVY_MAP");
+    tm.get(id)
+        .cloned()
+        .ok_or_else(|| ErrorKinds::NotExist(format!("Entry {id} is not available")))
+}
+
+/// remove_session takes the session for id out of the registry, closing it once any request
+/// still running against it has finished
+pub fn remove_session(id: &str) -> InternalCallResult<()> {
+    let session = lock_recover(&TANTIVY_MAP, "TANTIVY_MAP")
+        .remove(id)
+        .ok_or_else(|| ErrorKinds::NotExist(format!("Entry {id} is not available")))?;
+    close_session(id, &session);
+    Ok(())
+}
+
+fn close_session(id: &str, session: &Mutex<TantivySession>) {
+    lock_recover(session, id).close();
+}
+
+/// evict_idle_sessions removes and closes every session that has been idle longer than the
+/// configured ttl.  Sessions currently in use, and the session named by `keep`, are left alone.
+/// Nothing is done when the last sweep ran less than half the ttl ago.  Returns the ids of the
+/// evicted sessions.
+pub fn evict_idle_sessions(keep: Option<&str>) -> Vec<String> {
+    match idle_ttl() {
+        Some(ttl) if sweep_due(ttl) => evict_sessions_idle_for(ttl, keep),
+        _ => vec![],
+    }
+}
+
+// sweep_due claims the next sweep when half the ttl has passed since the last one, so only one
+// of the requests arriving together runs it
+pub(crate) fn sweep_due(ttl: Duration) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    let last = LAST_SWEEP_MS.load(Ordering::Relaxed);
+    if now.saturating_sub(last) < (ttl / 2).as_millis() as u64 {
+        return false;
+    }
+    LAST_SWEEP_MS
+        .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+}
+
+pub(crate) fn evict_sessions_idle_for(ttl: Duration, keep: Option<&str>) -> Vec<String> {
+    let evicted: Vec<(String, Arc<Mutex<TantivySession>>)> = {
+        let mut tm = lock_recover(&TANTIVY_MAP, "TANTIVY_MAP");
+        let expired: Vec<String> = tm
+            .iter()
+            .filter(|(id, _)| Some(id.as_str()) != keep)
+            .filter(|(_, s)| match s.try_lock() {
+                Ok(g) => g.last_used.elapsed() >= ttl,
+                Err(_) => false,
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| tm.remove(&id).map(|s| (id, s)))
+            .collect()
+    };
+    // close outside of TANTIVY_MAP, shutting down a writer can take a while
+    evicted
+        .into_iter()
+        .map(|(id, s)| {
+            info!("evicting idle session {id}");
+            close_session(&id, &s);
+            id
+        })
+        .collect()
+}
+
+/// handle_session serves the "session" obj, which manages the registry rather than a single
+/// session.  `info` and `close` act on the session named by the `session` param, defaulting to
+/// the id of the request.
+pub fn handle_session(
+    id: &str,
+    method: &str,
+    params: &serde_json::Value,
+) -> InternalCallResult<serde_json::Value> {
+    debug!("Session");
+    let target = params.get("session").and_then(|s| s.as_str()).unwrap_or(id);
+    match method {
+        "list" => {
+            let list: Vec<serde_json::Value> = sessions()
+                .iter()
+                .map(|(id, s)| match s.try_lock() {
+                    Ok(g) => json!({
+                        "id": id,
+                        "busy": false,
+                        "idle_secs": g.last_used.elapsed().as_secs(),
+                    }),
+                    Err(_) => json!({ "id": id, "busy": true }),
+                })
+                .collect();
+            Ok(json!({
+                "sessions": list,
+                "idle_ttl": IDLE_TTL_SECS.load(Ordering::Relaxed),
+            }))
+        }
+        "info" => {
+            let session = find_session(target)?;
+            let details = lock_recover(&session, target).session_info();
+            Ok(details)
+        }
+        "close" => {
+            remove_session(target)?;
+            Ok(json!({ "closed": target }))
+        }
+        "set_idle_ttl" => {
+            let secs = params
+                .get("seconds")
+                .ok_or_else(|| ErrorKinds::BadParams("expected seconds".to_string()))?
+                .as_u64()
+                .ok_or_else(|| ErrorKinds::BadParams("seconds is a u64".to_string()))?;
+            set_idle_ttl(secs);
+            Ok(json!({ "idle_ttl": secs }))
+        }
+        &_ => {
+            make_internal_json_error(ErrorKinds::UnRecognizedCommand(format!("session.{method}")))
+        }
+    }
+}
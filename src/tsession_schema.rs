@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::debug;
 use crate::make_internal_json_error;
+use crate::tsession_document::{json_to_doc, pending_error, pending_response};
 use crate::ErrorKinds;
 use crate::InternalCallResult;
 use crate::TantivySession;
@@ -9,9 +10,124 @@ use crate::TantivySession;
 extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
-use tantivy::schema::{Field, FieldEntry};
+use serde_json::json;
+use tantivy::schema::{Field, FieldEntry, Schema};
+use tantivy::{DocAddress, Document};
+
+/// schema_diff compares the schema of an existing index with the one requested for it.  It lists
+/// the fields only requested as added, those only on disk as removed and those whose id or
+/// options differ as changed, returning None when the schemas match.
+pub fn schema_diff(on_disk: &Schema, requested: &Schema) -> Option<serde_json::Value> {
+    let describe = |sc: &Schema| -> BTreeMap<String, serde_json::Value> {
+        sc.fields()
+            .map(|(f, e)| (e.name().to_string(), json!({ "field": f, "entry": e })))
+            .collect()
+    };
+    let disk = describe(on_disk);
+    let req = describe(requested);
+    let added: Vec<&serde_json::Value> = req
+        .iter()
+        .filter(|(name, _)| !disk.contains_key(*name))
+        .map(|(_, d)| d)
+        .collect();
+    let removed: Vec<&serde_json::Value> = disk
+        .iter()
+        .filter(|(name, _)| !req.contains_key(*name))
+        .map(|(_, d)| d)
+        .collect();
+    let changed: Vec<serde_json::Value> = req
+        .iter()
+        .filter_map(|(name, r)| {
+            disk.get(name)
+                .filter(|d| *d != r)
+                .map(|d| json!({ "name": name, "on_disk": d, "requested": r }))
+        })
+        .collect();
+    if added.is_empty() && removed.is_empty() && changed.is_empty() {
+        return None;
+    }
+    Some(json!({ "added": added, "removed": removed, "changed": changed }))
+}
+
+// field_name reads the field named in params, given either as a string or as the first element
+// of an array
+fn field_name(params: &serde_json::Value) -> InternalCallResult<&str> {
+    let v = params.get("field");
+    debug!("field={v:?}");
+    let name = match v {
+        Some(serde_json::Value::Array(a)) => a.first().and_then(|f| f.as_str()),
+        Some(f) => f.as_str(),
+        None => None,
+    };
+    match name {
+        Some(n) => Ok(n),
+        None => make_internal_json_error(ErrorKinds::BadParams(
+            "field must name a field in the schema".to_string(),
+        )),
+    }
+}
+
+// json_document reads the document param as a JSON object
+fn json_document(
+    params: &serde_json::Value,
+) -> InternalCallResult<serde_json::Map<String, serde_json::Value>> {
+    match params.get("document").and_then(|d| d.as_object()) {
+        Some(d) => Ok(d.clone()),
+        None => make_internal_json_error(ErrorKinds::BadParams(
+            "document must be a json object".to_string(),
+        )),
+    }
+}
 
 impl TantivySession {
+    // pending_or_stored returns the document addressed by params, a stored document when
+    // segment_ord is given and the 1-based pending doc_id otherwise
+    fn pending_or_stored(&self, params: &serde_json::Value) -> InternalCallResult<Document> {
+        let doc_id = match params.get("doc_id").and_then(|d| d.as_u64()) {
+            Some(d) => d,
+            None => {
+                return make_internal_json_error(ErrorKinds::BadParams(
+                    "doc_id must be an unsigned integer".to_string(),
+                ))
+            }
+        };
+        if let Some(segment_ord) = params.get("segment_ord").and_then(|s| s.as_u64()) {
+            let searcher = match &self.searcher {
+                Some(s) => s,
+                None => {
+                    return make_internal_json_error(ErrorKinds::BadInitialization(
+                        "stored documents need a searcher, call index_reader.searcher".to_string(),
+                    ))
+                }
+            };
+            // tantivy indexes its segment readers directly, check the address before it panics
+            let segments = searcher.segment_readers();
+            let segment = match segments.get(segment_ord as usize) {
+                Some(s) => s,
+                None => {
+                    return make_internal_json_error(ErrorKinds::BadParams(format!(
+                        "segment_ord {segment_ord} is out of range, the searcher has {} segments",
+                        segments.len()
+                    )))
+                }
+            };
+            if doc_id >= u64::from(segment.max_doc()) {
+                return make_internal_json_error(ErrorKinds::BadParams(format!(
+                    "doc_id {doc_id} is out of range, segment {segment_ord} has {} documents",
+                    segment.max_doc()
+                )));
+            }
+            return Ok(searcher.doc(DocAddress {
+                segment_ord: segment_ord as u32,
+                doc_id: doc_id as u32,
+            })?);
+        }
+        match self.doc.as_ref().and_then(|d| d.get(&(doc_id as usize))) {
+            Some(d) => Ok(d.clone()),
+            None => Err(pending_error(doc_id as usize, self.next_doc_id)),
+        }
+    }
+
     pub fn handler_schema(
         &mut self,
         method: &str,
@@ -19,32 +135,17 @@ impl TantivySession {
     ) -> InternalCallResult<u32> {
         debug!("Schema");
         let sc = match &self.schema {
-            Some(s) => s,
+            Some(s) => s.clone(),
             None => {
                 return make_internal_json_error(ErrorKinds::Search(
                     "handler schema called with no schema".to_string(),
                 ))
             }
         };
-        let ve = Vec::<serde_json::Value>::new();
-        fn get_fields<'a>(
-            params: &'a serde_json::Value,
-            empty: &'a Vec<serde_json::Value>,
-        ) -> &'a Vec<serde_json::Value> {
-            match params.as_object() {
-                Some(p) => {
-                    let v = p.get("field").unwrap();
-                    debug!("p={p:?} v={v}");
-                    p.get("field").and_then(|u| u.as_array()).unwrap_or(empty) // Default to an empty vector
-                }
-                None => empty, // Default values
-            }
-        }
 
         match method {
             "get_field_entry" => {
-                let fields = get_fields(&params, &ve);
-                let field = sc.get_field(fields[0].as_str().unwrap_or(""))?;
+                let field = sc.get_field(field_name(&params)?)?;
                 self.return_buffer = serde_json::to_string(sc.get_field_entry(field))?;
             }
             "num_fields" => {
@@ -59,37 +160,69 @@ impl TantivySession {
                 self.return_buffer = serde_json::to_string(&hashmap)?;
             }
             "get_field" => {
-                let fields = get_fields(&params, &ve);
-                self.return_buffer =
-                    serde_json::to_string(&sc.get_field(fields[0].as_str().unwrap_or(""))?)?;
+                self.return_buffer = serde_json::to_string(&sc.get_field(field_name(&params)?)?)?;
             }
             "convert_named_doc" => {
-                return Err(ErrorKinds::NotExist(
-                    "convert_named_doc not implemented".to_string(),
-                ))
+                // a named document maps each field name to its list of values, converting it
+                // through the schema gives every value the type of its field
+                let named = json_document(&params)?;
+                if let Some((name, _)) = named.iter().find(|(_, v)| !v.is_array()) {
+                    return make_internal_json_error(ErrorKinds::BadParams(format!(
+                        "named document values must be arrays, {name} is not"
+                    )));
+                }
+                let doc = json_to_doc(&sc, named)?;
+                self.return_buffer = pending_response(self.push_document(doc));
             }
             "to_named_doc" => {
-                return Err(ErrorKinds::NotExist(
-                    "to_named_doc not implemented".to_string(),
-                ))
+                let doc = self.pending_or_stored(&params)?;
+                self.return_buffer = serde_json::to_string(&sc.to_named_doc(&doc))?;
+            }
+            "to_json" => {
+                let doc = self.pending_or_stored(&params)?;
+                self.return_buffer = sc.to_json(&doc);
             }
-            "to_json" => return Err(ErrorKinds::NotExist("to_json not implemented".to_string())),
             "parse_document" => {
-                return Err(ErrorKinds::NotExist(
-                    "parse_document not implemented".to_string(),
-                ))
+                let text = match params.get("document").and_then(|d| d.as_str()) {
+                    Some(t) => t,
+                    None => {
+                        return make_internal_json_error(ErrorKinds::BadParams(
+                            "document must be a string of json".to_string(),
+                        ))
+                    }
+                };
+                let obj = serde_json::from_str(text).map_err(|e| {
+                    ErrorKinds::BadParams(format!("document is not a json object: {e}"))
+                })?;
+                let doc = json_to_doc(&sc, obj)?;
+                self.return_buffer = pending_response(self.push_document(doc));
             }
             "json_object_to_doc" => {
-                return Err(ErrorKinds::NotExist(
-                    "json_object_to_doc not implemented".to_string(),
-                ))
+                let doc = json_to_doc(&sc, json_document(&params)?)?;
+                self.return_buffer = pending_response(self.push_document(doc));
             }
             "find_field" => {
-                return Err(ErrorKinds::NotExist(
-                    "find_field not implemented".to_string(),
-                ))
+                let path = field_name(&params)?;
+                let (field, rest) = match sc.find_field(path) {
+                    Some(f) => f,
+                    None => {
+                        return make_internal_json_error(ErrorKinds::NotExist(format!(
+                            "no field matches {path}"
+                        )))
+                    }
+                };
+                self.return_buffer = json!({
+                    "field": field,
+                    "name": sc.get_field_name(field),
+                    "path": rest,
+                })
+                .to_string();
+            }
+            &_ => {
+                return make_internal_json_error(ErrorKinds::UnRecognizedCommand(format!(
+                    "schema.{method}"
+                )))
             }
-            &_ => {}
         };
         Ok(0)
     }
@@ -189,8 +189,8 @@ impl TantivySession {
     ) -> InternalCallResult<u32> {
         debug!("FuzzySearcher");
         if method != "fuzzy_searcher" {
-            return Err(ErrorKinds::NotExist(format!(
-                "expecting method fuzzy_searcher found {method}"
+            return Err(ErrorKinds::UnRecognizedCommand(format!(
+                "fuzzy_searcher.{method}"
             )));
         }
         const DEF_LIMIT: u64 = 2;
@@ -551,9 +551,7 @@ impl TantivySession {
         method: &str,
         params: serde_json::Value,
     ) -> InternalCallResult<u32> {
-        debug!("Searcher");
-        let s = format!("{}", params);
-        println!("{}", s);
+        debug!("Searcher params={params}");
         match method {
             "search" => self.do_search(params),
             "search_raw" => self.do_raw_search(params),
@@ -561,7 +559,9 @@ impl TantivySession {
             "get_document" => self.do_get_document(params),
             _ => {
                 error!("unknown method {method}");
-                Err(ErrorKinds::NotExist(format!("unknown method {method}")))
+                Err(ErrorKinds::UnRecognizedCommand(format!(
+                    "searcher.{method}"
+                )))
             }
         }
     }
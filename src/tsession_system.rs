@@ -0,0 +1,238 @@
+use crate::debug;
+use crate::journal;
+use crate::make_internal_json_error;
+use crate::ErrorKinds;
+use crate::InternalCallResult;
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+use serde_json::json;
+use std::path::Path;
+use std::time::Duration;
+
+/// Capability describes a method served by tantivy_jpc along with the params it reads
+pub struct Capability {
+    pub obj: &'static str,
+    pub method: &'static str,
+    pub params: &'static [&'static str],
+}
+
+const JSON_FIELD_PARAMS: &[&str] = &[
+    "name",
+    "type",
+    "stored",
+    "indexed",
+    "fast",
+    "tokenizer",
+    "basic",
+    "record",
+    "fieldnorms",
+    "expand_dots",
+];
+const TEXT_FIELD_PARAMS: &[&str] = &[
+    "name",
+    "type",
+    "stored",
+    "indexed",
+    "fast",
+    "tokenizer",
+    "basic",
+    "record",
+    "fieldnorms",
+];
+const NUMERIC_FIELD_PARAMS: &[&str] = &[
+    "name",
+    "type",
+    "stored",
+    "indexed",
+    "fast",
+    "fieldnorms",
+    "coerce",
+];
+const DATE_FIELD_PARAMS: &[&str] = &[
+    "name",
+    "type",
+    "stored",
+    "indexed",
+    "fast",
+    "fieldnorms",
+    "precision",
+];
+const SIMPLE_FIELD_PARAMS: &[&str] = &["name", "type", "stored", "indexed", "fast"];
+const DOC_FIELD_PARAMS: &[&str] = &["doc_id", "field", "value", "lenient"];
+
+macro_rules! cap {
+    ($obj:expr, $method:expr, $params:expr) => {
+        Capability {
+            obj: $obj,
+            method: $method,
+            params: $params,
+        }
+    };
+}
+
+/// CAPABILITIES lists every obj/method pair accepted by tantivy_jpc.  Requests naming a pair that
+/// is not listed here are answered with UnRecognizedCommand before reaching a handler, so new
+/// methods must be added here as well as to their handler.
+pub const CAPABILITIES: &[Capability] = &[
+    cap!("builder", "add_text_field", TEXT_FIELD_PARAMS),
+    cap!("builder", "add_json_field", JSON_FIELD_PARAMS),
+    cap!("builder", "add_date_field", DATE_FIELD_PARAMS),
+    cap!("builder", "add_u64_field", NUMERIC_FIELD_PARAMS),
+    cap!("builder", "add_i64_field", NUMERIC_FIELD_PARAMS),
+    cap!("builder", "add_f64_field", NUMERIC_FIELD_PARAMS),
+    cap!("builder", "add_bool_field", NUMERIC_FIELD_PARAMS),
+    cap!("builder", "add_bytes_field", SIMPLE_FIELD_PARAMS),
+    cap!("builder", "add_ip_addr_field", SIMPLE_FIELD_PARAMS),
+    cap!("builder", "add_facet_field", SIMPLE_FIELD_PARAMS),
+    cap!("builder", "build", &[]),
+    cap!("builder", "from_json", &["schema"]),
+    cap!("schema", "get_field_entry", &["field"]),
+    cap!("schema", "num_fields", &[]),
+    cap!("schema", "fields", &[]),
+    cap!("schema", "get_field", &["field"]),
+    cap!("schema", "find_field", &["field"]),
+    cap!("schema", "parse_document", &["document"]),
+    cap!("schema", "json_object_to_doc", &["document"]),
+    cap!("schema", "convert_named_doc", &["document"]),
+    cap!("schema", "to_named_doc", &["doc_id", "segment_ord"]),
+    cap!("schema", "to_json", &["doc_id", "segment_ord"]),
+    cap!("document", "create", &[]),
+    cap!("document", "add_json_doc", &["document", "add"]),
+    cap!("document", "add_text", DOC_FIELD_PARAMS),
+    cap!("document", "add_json", DOC_FIELD_PARAMS),
+    cap!("document", "add_int", DOC_FIELD_PARAMS),
+    cap!("document", "add_uint", DOC_FIELD_PARAMS),
+    cap!("document", "add_f64", DOC_FIELD_PARAMS),
+    cap!("document", "add_bool", DOC_FIELD_PARAMS),
+    cap!(
+        "document",
+        "add_date",
+        &["doc_id", "field", "value", "precision"]
+    ),
+    cap!("document", "add_bytes", DOC_FIELD_PARAMS),
+    cap!("document", "add_ip_addr", DOC_FIELD_PARAMS),
+    cap!("document", "add_ip", DOC_FIELD_PARAMS),
+    cap!("document", "add_facet", DOC_FIELD_PARAMS),
+    cap!("document", "add_pretokenized", DOC_FIELD_PARAMS),
+    cap!("document", "list", &[]),
+    cap!("document", "inspect", &["doc_id"]),
+    cap!("document", "clear_field", &["doc_id", "field"]),
+    cap!("document", "discard", &["doc_id"]),
+    cap!("index", "create", &["directory", "memsize", "mode"]),
+    cap!("index", "reader_builder", &[]),
+    cap!("index", "set_multithread_executor", &["max_threads"]),
+    cap!("indexwriter", "add_document", &["id"]),
+    cap!("indexwriter", "delete_term", &["field", "term"]),
+    cap!(
+        "indexwriter",
+        "ingest_ndjson",
+        &["data", "path", "commit_every", "max_errors"]
+    ),
+    cap!("indexwriter", "commit", &[]),
+    cap!("index_reader", "searcher", &[]),
+    cap!("query_parser", "for_index", &["fields"]),
+    cap!("query_parser", "parse_query", &["query"]),
+    cap!("query_parser", "parse_fuzzy_query", &["field", "term"]),
+    cap!(
+        "searcher",
+        "search",
+        &["top_limit", "offset", "explain", "scoring", "snippet_field"]
+    ),
+    cap!("searcher", "search_raw", &["limit"]),
+    cap!("searcher", "docset", &["top_limit", "offset", "scoring"]),
+    cap!(
+        "searcher",
+        "get_document",
+        &["segment_ord", "doc_id", "score", "explain", "snippet_field"]
+    ),
+    cap!("fuzzy_searcher", "fuzzy_searcher", &["top_limit"]),
+    cap!("session", "list", &[]),
+    cap!("session", "info", &["session"]),
+    cap!("session", "close", &["session"]),
+    cap!("session", "set_idle_ttl", &["seconds"]),
+    cap!("system", "version", &[]),
+    cap!("system", "capabilities", &[]),
+    cap!("system", "journal_start", &["path"]),
+    cap!("system", "journal_stop", &[]),
+    cap!("system", "data_map", &["reclaim_after_secs"]),
+    cap!("system", "metrics", &[]),
+];
+
+/// has_method reports whether method is served for obj
+pub fn has_method(obj: &str, method: &str) -> bool {
+    CAPABILITIES
+        .iter()
+        .any(|c| c.obj == obj && c.method == method)
+}
+
+/// method_params returns the params documented for obj.method, empty when it is not served
+pub fn method_params(obj: &str, method: &str) -> &'static [&'static str] {
+    CAPABILITIES
+        .iter()
+        .find(|c| c.obj == obj && c.method == method)
+        .map_or(&[], |c| c.params)
+}
+
+// check_method fails with UnRecognizedCommand for any obj/method pair that is not served
+pub(crate) fn check_method(obj: &str, method: &str) -> InternalCallResult<()> {
+    if has_method(obj, method) {
+        return Ok(());
+    }
+    make_internal_json_error(ErrorKinds::UnRecognizedCommand(format!("{obj}.{method}")))
+}
+
+fn version() -> serde_json::Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "tantivy_rev": env!("TANTIVY_REV"),
+        "protocols": ["1.0", crate::jsonrpc::JSONRPC_VERSION],
+    })
+}
+
+/// handle_system serves the "system" obj describing and controlling the library itself
+pub fn handle_system(
+    method: &str,
+    params: &serde_json::Value,
+) -> InternalCallResult<serde_json::Value> {
+    debug!("System");
+    match method {
+        "version" => Ok(version()),
+        "capabilities" => {
+            let methods: Vec<serde_json::Value> = CAPABILITIES
+                .iter()
+                .map(|c| json!({ "obj": c.obj, "method": c.method, "params": c.params }))
+                .collect();
+            let mut resp = version();
+            resp["methods"] = json!(methods);
+            Ok(resp)
+        }
+        "journal_start" => {
+            let path = params
+                .get("path")
+                .and_then(|p| p.as_str())
+                .ok_or_else(|| ErrorKinds::BadParams("expected path".to_string()))?;
+            journal::start(Path::new(path))?;
+            Ok(json!({ "journal": path }))
+        }
+        "journal_stop" => Ok(json!({ "journal": journal::stop() })),
+        "metrics" => Ok(json!({ "metrics": crate::metrics::render() })),
+        "data_map" => {
+            let reclaim_after = match params.get("reclaim_after_secs") {
+                Some(v) => Some(
+                    v.as_f64()
+                        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                        .ok_or_else(|| {
+                            ErrorKinds::BadParams(
+                                "reclaim_after_secs is a positive number".to_string(),
+                            )
+                        })?,
+                ),
+                None => None,
+            };
+            Ok(crate::data_map_stats(reclaim_after))
+        }
+        &_ => make_internal_json_error(ErrorKinds::UnRecognizedCommand(format!("system.{method}"))),
+    }
+}
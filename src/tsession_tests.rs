@@ -416,6 +416,36 @@ pub mod tests {
                 vec![]
             }
         }
+        // call_json makes a jpc 1.0 call and parses its response, the error object included
+        pub fn call_json(
+            &self,
+            object: &str,
+            method: &str,
+            params: serde_json::Value,
+        ) -> serde_json::Value {
+            serde_json::from_slice(&self.call_jpc(
+                object.to_string(),
+                method.to_string(),
+                params,
+                true,
+            ))
+            .unwrap()
+        }
+        pub fn call_jsonrpc(&self, request: &str) -> serde_json::Value {
+            let my_ret_ptr = &mut usize::default();
+            let mut p: *const u8 = std::ptr::null_mut();
+            let mut sp = request.as_bytes().to_vec();
+            info!("calling tantivy-jpc jsonrpc = {}", request);
+            let iret: i64;
+            unsafe {
+                iret = tantivy_jpc(sp.as_mut_ptr(), sp.len(), &mut p, my_ret_ptr);
+            }
+            let sl = unsafe { self.ptr_to_vec(p, my_ret_ptr) };
+            defer! {
+                unsafe{free_data(iret);}
+            }
+            serde_json::from_slice(&sl).unwrap()
+        }
         pub fn add_text_field(
             &mut self,
             name: String,
@@ -1533,32 +1563,50 @@ pub mod tests {
             &ctx,
             "builder".to_string(),
             "some_function".to_string(),
-            "handle builder error=BadParams : `Unknown method some_function`",
+            "handle builder error=Not Recognized : `builder.some_function`",
         );
         base_tester(
             &ctx,
             "query_parser".to_string(),
             "some_function".to_string(),
-            "handle query parser error=BadParams : `Unknown method some_function`",
+            "handle query parser error=Not Recognized : `query_parser.some_function`",
         );
         base_tester(
             &ctx,
             "index".to_string(),
             "some_function".to_string(),
-            "handle index error=BadParams : `Finalized : `A schema must be created before an index``",
+            "handle index error=Not Recognized : `index.some_function`",
         );
         base_tester(
             &ctx,
             "searcher".to_string(),
             "some_function".to_string(),
-            "handle searcher error=NotExist : `unknown method some_function`",
+            "handle searcher error=Not Recognized : `searcher.some_function`",
         );
         base_tester(
             &ctx,
             "fuzzy_searcher".to_string(),
             "some_function".to_string(),
-            "handle searcher error=NotExist : `expecting method fuzzy_searcher found some_function`",
+            "handle searcher error=Not Recognized : `fuzzy_searcher.some_function`",
         );
+        for obj in [
+            "document",
+            "schema",
+            "indexwriter",
+            "index_reader",
+            "session",
+            "system",
+        ] {
+            let s = &ctx.call_jpc(
+                obj.to_string(),
+                "some_function".to_string(),
+                json!({}),
+                true,
+            );
+            let r: serde_json::Value = serde_json::from_slice(s).unwrap();
+            assert_eq!(r["kind"], "UnRecognizedCommand");
+            assert_eq!(r["data"]["obj"], obj);
+        }
     }
 
     #[test]
@@ -1674,4 +1722,1804 @@ pub mod tests {
             Err(e) => panic!("exception = {e}"),
         };
     }
+
+    #[test]
+    fn jsonrpc_mode() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let r = ctx.call_jsonrpc(
+            &json!({
+                "jsonrpc": "2.0",
+                "id": 7,
+                "session": ctx.id,
+                "method": "builder.add_text_field",
+                "params": {"name": "title", "type": 2, "stored": true, "indexed": true},
+            })
+            .to_string(),
+        );
+        assert_eq!(r["jsonrpc"], "2.0");
+        assert_eq!(r["id"], 7);
+        assert_eq!(r["result"]["field"], 0);
+        let r = ctx.call_jsonrpc(
+            &json!({
+                "jsonrpc": "2.0",
+                "id": "abc",
+                "session": ctx.id,
+                "method": "nothing.some_function",
+            })
+            .to_string(),
+        );
+        assert_eq!(r["id"], "abc");
+        assert_eq!(r["error"]["code"], crate::jsonrpc::METHOD_NOT_FOUND);
+        let r = ctx.call_jsonrpc(
+            &json!({
+                "jsonrpc": "2.0",
+                "id": 8,
+                "method": "builder.build",
+            })
+            .to_string(),
+        );
+        assert_eq!(r["error"]["code"], crate::jsonrpc::INVALID_REQUEST);
+        let r = ctx.call_jsonrpc(r#"{"jsonrpc": "2.0", "method"#);
+        assert_eq!(r["id"], serde_json::Value::Null);
+        assert_eq!(r["error"]["code"], crate::jsonrpc::PARSE_ERROR);
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn jsonrpc_key_in_jpc_params() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_json_field(
+            "attrs".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        let td = ctx.build(true).unwrap();
+        // a jpc 1.0 request stays jpc 1.0 when its params carry a jsonrpc key
+        let r = td.ctx.call_jsonrpc(
+            &json!({
+                "id": td.ctx.id,
+                "jpc": "1.0",
+                "obj": "document",
+                "method": "add_json_doc",
+                "params": {"document": {"attrs": {"jsonrpc": "2.0", "id": 1}}},
+            })
+            .to_string(),
+        );
+        assert_eq!(r, json!({"doc_id": 1, "document_count": 1}));
+        let r = td.ctx.call_jsonrpc(
+            &json!({
+                "id": td.ctx.id,
+                "jpc": "1.0",
+                "obj": "document",
+                "method": "add_json",
+                "params": {"doc_id": 1, "field": "attrs", "value": {"jsonrpc": "2.0"}},
+            })
+            .to_string(),
+        );
+        assert!(r.get("error").is_none(), "{r}");
+        let _ = crate::do_term(&td.ctx.id);
+    }
+
+    #[test]
+    fn jsonrpc_notifications() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let (code, resp) = crate::process_request(
+            &json!({"jsonrpc": "2.0", "session": ctx.id, "method": "builder.add_text_field",
+                    "params": {"name": "title", "type": 2, "stored": true}})
+            .to_string(),
+        );
+        assert_eq!((code, resp.as_str()), (0, ""));
+        let (code, resp) = crate::process_request(
+            &json!({"jsonrpc": "2.0", "session": ctx.id, "method": "builder.nothing"}).to_string(),
+        );
+        assert_eq!((code, resp.as_str()), (-1, ""));
+        let (_, resp) = crate::process_request(
+            &json!([
+                {"jsonrpc": "2.0", "session": ctx.id, "method": "session.info"},
+                {"jsonrpc": "2.0", "id": 5, "session": ctx.id, "method": "builder.build"},
+            ])
+            .to_string(),
+        );
+        let r: serde_json::Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(r.as_array().unwrap().len(), 1);
+        assert_eq!(r[0]["id"], 5);
+        assert_eq!(r[0]["result"]["schema"].as_array().unwrap().len(), 1);
+        let (_, resp) = crate::process_request(
+            &json!([{"jsonrpc": "2.0", "session": ctx.id, "method": "session.info"}]).to_string(),
+        );
+        assert_eq!(resp, "");
+
+        let (code, resp) = crate::process_request("[]");
+        assert_eq!(code, -1);
+        let r: serde_json::Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(r["jsonrpc"], "2.0");
+        assert_eq!(r["id"], serde_json::Value::Null);
+        assert_eq!(r["error"]["code"], crate::jsonrpc::INVALID_REQUEST);
+
+        // parse errors pick the envelope from top level keys only
+        let r = ctx.call_jsonrpc(r#"{"id": "x", "jpc": "1.0", "params": {"jsonrpc": "2.0"}, "obj"#);
+        assert_eq!(r["kind"], "Parse");
+        let r = ctx.call_jsonrpc(r#"[{"jsonrpc": "2.0", "id": 1, "meth"#);
+        assert_eq!(r["error"]["code"], crate::jsonrpc::PARSE_ERROR);
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn batch_requests() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let batch = json!([
+            {
+                "id": ctx.id,
+                "jpc": "1.0",
+                "obj": "builder",
+                "method": "add_text_field",
+                "params": {"name": "title", "type": 2, "stored": true, "indexed": true},
+            },
+            {
+                "id": ctx.id,
+                "jpc": "1.0",
+                "obj": "builder",
+                "method": "add_text_field",
+                "params": {"name": "bad"},
+            },
+            {
+                "jsonrpc": "2.0",
+                "id": 3,
+                "session": ctx.id,
+                "method": "builder.add_text_field",
+                "params": {"name": "body", "type": 2, "stored": true, "indexed": true},
+            },
+        ]);
+        let r = ctx.call_jsonrpc(&batch.to_string());
+        let results = r.as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["result"]["field"], 0);
+        assert!(results[1]["error"].is_string());
+        assert_eq!(results[2]["id"], 3);
+        assert_eq!(results[2]["result"]["field"], 1);
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn structured_error_codes() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let s = &ctx.call_jpc(
+            "builder".to_string(),
+            "add_text_field".to_string(),
+            json!({"name": "title"}),
+            true,
+        );
+        let r: serde_json::Value = serde_json::from_slice(s).unwrap();
+        assert_eq!(
+            r["error"],
+            "handle builder error=BadParams : `type must be specified`"
+        );
+        assert_eq!(r["kind"], "BadParams");
+        assert_eq!(r["code"], ErrorKinds::BadParams(String::new()).code());
+        assert_eq!(r["data"]["obj"], "builder");
+        assert_eq!(r["data"]["method"], "add_text_field");
+        assert_eq!(r["data"]["field"], "title");
+        let s = &ctx.call_jpc(
+            "nothing".to_string(),
+            "some_function".to_string(),
+            json!({}),
+            true,
+        );
+        let r: serde_json::Value = serde_json::from_slice(s).unwrap();
+        assert_eq!(r["kind"], "UnRecognizedCommand");
+        assert_eq!(r["code"], crate::jsonrpc::METHOD_NOT_FOUND);
+        let r = ctx.call_jsonrpc(
+            &json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "session": ctx.id,
+                "method": "searcher.search",
+            })
+            .to_string(),
+        );
+        assert_eq!(
+            r["error"]["code"],
+            ErrorKinds::NotExist(String::new()).code()
+        );
+        assert_eq!(r["error"]["message"], "NotExist : `dyn query not created`");
+        assert_eq!(r["error"]["data"]["kind"], "NotExist");
+        assert_eq!(r["error"]["data"]["obj"], "searcher");
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn sessions_lock_independently() {
+        crate::test_init();
+        let held = FakeContext::new();
+        let session = crate::get_session(&held.id).unwrap();
+        let guard = session.lock().unwrap();
+        // a busy session must not hold up requests against another one
+        let worker = std::thread::spawn(|| {
+            let mut ctx = FakeContext::new();
+            let f = ctx.add_text_field(
+                "title".to_string(),
+                2,
+                true,
+                true,
+                "en_stem_with_stop_words".to_string(),
+                false,
+            );
+            let _ = crate::do_term(&ctx.id);
+            f
+        });
+        assert_eq!(worker.join().unwrap(), 0);
+        drop(guard);
+        let _ = crate::do_term(&held.id);
+    }
+
+    #[test]
+    fn session_registry() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        assert_eq!(
+            ctx.add_text_field(
+                "title".to_string(),
+                2,
+                true,
+                true,
+                "en_stem_with_stop_words".to_string(),
+                false,
+            ),
+            0
+        );
+        let list = ctx.call_json("session", "list", json!({}));
+        assert!(list["sessions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|s| s["id"] == ctx.id));
+        let info = ctx.call_json("session", "info", json!({}));
+        assert_eq!(info["id"], ctx.id);
+        assert_eq!(info["components"]["builder"], true);
+        assert_eq!(info["components"]["index"], false);
+        let resp = ctx.call_jsonrpc(
+            &json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "session.info",
+                "params": {"session": ctx.id},
+            })
+            .to_string(),
+        );
+        assert_eq!(resp["result"]["id"], ctx.id);
+        let closed = ctx.call_json("session", "close", json!({}));
+        assert_eq!(closed["closed"], ctx.id);
+        let err = ctx.call_json("session", "info", json!({}));
+        assert_eq!(err["kind"], "NotExist");
+        assert!(crate::do_term(&ctx.id).is_err());
+    }
+
+    #[test]
+    fn idle_sweep_rate_limited() {
+        crate::test_init();
+        let ttl = std::time::Duration::from_secs(3600);
+        // eviction is disabled in tests, nothing else claims a sweep
+        assert!(crate::tsession_registry::sweep_due(ttl));
+        assert!(!crate::tsession_registry::sweep_due(ttl));
+        assert!(crate::tsession_registry::evict_idle_sessions(None).is_empty());
+    }
+
+    #[test]
+    fn idle_sessions_evicted() {
+        crate::test_init();
+        let idle = FakeContext::new();
+        let kept = FakeContext::new();
+        for id in [&idle.id, &kept.id] {
+            let session = crate::get_session(id).unwrap();
+            session.lock().unwrap().last_used -= std::time::Duration::from_secs(7200);
+        }
+        let evicted = crate::tsession_registry::evict_sessions_idle_for(
+            std::time::Duration::from_secs(3600),
+            Some(&kept.id),
+        );
+        assert!(evicted.contains(&idle.id));
+        assert!(!evicted.contains(&kept.id));
+        assert!(crate::do_term(&idle.id).is_err());
+        assert!(crate::do_term(&kept.id).is_ok());
+    }
+
+    #[test]
+    fn evicted_session_not_used() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let session = crate::get_session(&ctx.id).unwrap();
+        let mut guard = session.lock().unwrap();
+        guard.return_buffer = "evicted".to_string();
+        let id = ctx.id.clone();
+        let worker = std::thread::spawn(move || {
+            crate::with_session(&id, |s| s.return_buffer.clone()).unwrap()
+        });
+        // wait for the worker to hold the session, then evict it before the worker gets the lock
+        while std::sync::Arc::strong_count(&session) < 3 {
+            std::thread::yield_now();
+        }
+        crate::lock_recover(&crate::TANTIVY_MAP, "TANTIVY_MAP").remove(&ctx.id);
+        drop(guard);
+        assert_ne!(worker.join().unwrap(), "evicted");
+        assert!(crate::do_term(&ctx.id).is_ok());
+    }
+
+    #[test]
+    fn panics_and_poison_recovered() {
+        crate::test_init();
+        match crate::catch_panic(|| -> i64 { panic!("boom") }) {
+            Err(ek) => {
+                assert_eq!(ek.kind(), "Panic");
+                assert_eq!(ek.to_string(), "Panic : `boom`");
+            }
+            Ok(_) => panic!("expected the panic to be caught"),
+        }
+        let ctx = FakeContext::new();
+        let session = crate::get_session(&ctx.id).unwrap();
+        let poison = session.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poison.lock().unwrap();
+            panic!("poisoning session");
+        })
+        .join();
+        assert!(session.is_poisoned());
+        let info = ctx.call_json("session", "info", json!({}));
+        assert_eq!(info["id"], ctx.id);
+        assert!(!session.is_poisoned());
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn handler_panic_through_ffi() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_text_field(
+            "title".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        let mut td = ctx.build(true).unwrap();
+        let c = td.ctx.clone();
+        let doc1 = td.create().unwrap();
+        td.add_text(0, "Of Mice and Men".to_string(), doc1 as u32);
+        let mut ti = td.create_index().unwrap();
+        ti.add_document(doc1 as i32).unwrap();
+        ti.commit().unwrap();
+        let mut rb = ti.reader_builder().unwrap();
+        let mut qp = rb.searcher().unwrap();
+        qp.for_index(vec!["title".to_string()]).unwrap();
+        let mut searcher = qp.parse_query("mice".to_string()).unwrap();
+        // tantivy asserts the limit of a TopDocs collector is positive
+        let r = c.call_json("searcher", "search", json!({"top_limit": 0}));
+        assert_eq!(r["kind"], "Panic", "{r}");
+        assert_eq!(r["code"], ErrorKinds::Panic(String::new()).code());
+        let sres = searcher.search(10, true, vec![]).unwrap();
+        let res: Vec<ResultElement> = serde_json::from_str(&sres).unwrap();
+        assert_eq!(res.len(), 1);
+        let _ = crate::do_term(&c.id);
+    }
+
+    #[test]
+    fn system_version_and_capabilities() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let s = &ctx.call_jpc("system".to_string(), "version".to_string(), json!({}), true);
+        let v: serde_json::Value = serde_json::from_slice(s).unwrap();
+        assert_eq!(v["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!v["tantivy_rev"].as_str().unwrap().is_empty());
+        let r = ctx.call_jsonrpc(
+            &json!({"jsonrpc": "2.0", "id": 1, "method": "system.capabilities"}).to_string(),
+        );
+        let methods = r["result"]["methods"].as_array().unwrap();
+        assert_eq!(methods.len(), crate::tsession_system::CAPABILITIES.len());
+        assert!(methods.iter().any(|m| m["obj"] == "searcher"
+            && m["method"] == "search"
+            && m["params"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("top_limit"))));
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn server_lines() {
+        use std::io::{BufRead, Write};
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let requests = [
+            json!({"id": ctx.id, "jpc": "1.0", "obj": "builder", "method": "add_text_field",
+                   "params": {"name": "title", "type": 2, "stored": true}})
+            .to_string(),
+            String::new(),
+            json!({"jsonrpc": "2.0", "id": 2, "session": ctx.id, "method": "builder.build"})
+                .to_string(),
+            "not json".to_string(),
+        ]
+        .join("\n");
+        let mut out = Vec::<u8>::new();
+        crate::server::serve(std::io::Cursor::new(requests), &mut out).unwrap();
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["field"], 0);
+        assert_eq!(lines[1]["id"], 2);
+        assert!(lines[1]["result"]["schema"].is_array());
+        assert_eq!(lines[2]["kind"], "Parse");
+
+        let dir = TempDir::new("server").unwrap();
+        let path = dir.path().join("jpc.sock");
+        let listen = path.clone();
+        std::thread::spawn(move || crate::server::serve_unix(&listen));
+        let mut conn = None;
+        for _ in 0..100 {
+            if let Ok(c) = std::os::unix::net::UnixStream::connect(&path) {
+                conn = Some(c);
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let mut conn = conn.unwrap();
+        writeln!(
+            conn,
+            "{}",
+            json!({"jsonrpc": "2.0", "id": 3, "method": "system.version"})
+        )
+        .unwrap();
+        let mut line = String::new();
+        std::io::BufReader::new(&conn).read_line(&mut line).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["result"]["version"], env!("CARGO_PKG_VERSION"));
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn journal_and_replay() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let dir = TempDir::new("journal").unwrap();
+        let path = dir.path().join("requests.journal");
+        let started = ctx.call_jsonrpc(
+            &json!({"jsonrpc": "2.0", "id": 1, "method": "system.journal_start",
+                    "params": {"path": path}})
+            .to_string(),
+        );
+        assert_eq!(started["result"]["journal"], json!(path));
+        ctx.call_jpc("session".to_string(), "info".to_string(), json!({}), true);
+        let stopped = ctx.call_jsonrpc(
+            &json!({"jsonrpc": "2.0", "id": 2, "method": "system.journal_stop"}).to_string(),
+        );
+        assert_eq!(stopped["result"]["journal"], json!(path));
+        // other tests run concurrently and may be journaled too, only look for this session
+        let recorded: Vec<crate::journal::JournalEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .filter(|e: &crate::journal::JournalEntry| e.request["id"] == ctx.id)
+            .collect();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].request["obj"], "session");
+        assert_eq!(recorded[0].code, 0);
+
+        let original = TempDir::new("original").unwrap();
+        let envelope = |obj: &str, method: &str, params: serde_json::Value| json!({"id": "recorded", "jpc": "1.0", "obj": obj, "method": method, "params": params});
+        let entries = [
+            (
+                envelope(
+                    "builder",
+                    "add_text_field",
+                    json!({"name": "title", "type": 2, "stored": true, "indexed": true}),
+                ),
+                0,
+            ),
+            (envelope("builder", "build", json!({})), 0),
+            (
+                envelope("index", "create", json!({"directory": original.path()})),
+                0,
+            ),
+            (envelope("document", "create", json!({})), 0),
+            (
+                envelope(
+                    "document",
+                    "add_text",
+                    json!({"field": 0, "value": "replayed", "doc_id": 1}),
+                ),
+                0,
+            ),
+            (envelope("indexwriter", "add_document", json!({"id": 1})), 0),
+            (envelope("indexwriter", "commit", json!({})), 0),
+            (
+                envelope("system", "journal_start", json!({"path": path})),
+                0,
+            ),
+            (envelope("builder", "some_function", json!({})), -1),
+            (envelope("index", "reader_builder", json!({})), -1),
+        ];
+        let journal = dir.path().join("replay.journal");
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|(request, code)| json!({"ts": 0, "code": code, "request": request}).to_string())
+            .collect();
+        std::fs::write(&journal, lines.join("\n")).unwrap();
+        let fresh = dir.path().join("fresh");
+        let report = crate::journal::replay(&journal, &fresh).unwrap();
+        assert_eq!(report.replayed, 9);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].line, 10);
+        assert_eq!(report.mismatches[0].got, 0);
+        assert!(std::fs::read_dir(fresh.join("index-0")).unwrap().count() > 0);
+        assert_eq!(std::fs::read_dir(original.path()).unwrap().count(), 0);
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn caller_buffer() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let request =
+            json!({"jsonrpc": "2.0", "id": 1, "method": "system.capabilities"}).to_string();
+        let mut out = vec![0_u8; 16];
+        let mut out_len = 0_usize;
+        let mut status = 1_i64;
+        let handle = unsafe {
+            crate::tantivy_jpc_buf(
+                request.as_ptr(),
+                request.len(),
+                out.as_mut_ptr(),
+                out.len(),
+                &mut out_len,
+                &mut status,
+            )
+        };
+        assert!(handle > 0);
+        assert_eq!(status, 0);
+        assert!(out_len > out.len());
+        let mut again = 0_usize;
+        assert_eq!(
+            unsafe { crate::read_data(handle, out.as_mut_ptr(), out.len(), &mut again) },
+            -1
+        );
+        assert_eq!(again, out_len);
+        out.resize(out_len, 0);
+        assert_eq!(
+            unsafe { crate::read_data(handle, out.as_mut_ptr(), out.len(), &mut again) },
+            0
+        );
+        let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert!(v["result"]["methods"].is_array());
+        assert_eq!(unsafe { free_data(handle) }, -1);
+
+        let request = json!({"jsonrpc": "2.0", "id": 2, "method": "system.version"}).to_string();
+        let mut out = vec![0_u8; 4096];
+        let code = unsafe {
+            crate::tantivy_jpc_buf(
+                request.as_ptr(),
+                request.len(),
+                out.as_mut_ptr(),
+                out.len(),
+                &mut out_len,
+                &mut status,
+            )
+        };
+        assert_eq!(code, 0);
+        assert_eq!(status, 0);
+        let v: serde_json::Value = serde_json::from_slice(&out[..out_len]).unwrap();
+        assert_eq!(v["id"], 2);
+        let request = json!({"jsonrpc": "2.0", "id": 3, "method": "system.nothing"}).to_string();
+        let code = unsafe {
+            crate::tantivy_jpc_buf(
+                request.as_ptr(),
+                request.len(),
+                out.as_mut_ptr(),
+                out.len(),
+                &mut out_len,
+                &mut status,
+            )
+        };
+        assert_eq!(code, -1);
+        assert_eq!(status, -1);
+        let v: serde_json::Value = serde_json::from_slice(&out[..out_len]).unwrap();
+        assert_eq!(v["error"]["code"], crate::jsonrpc::METHOD_NOT_FOUND);
+        // a failed call whose response does not fit still reports the failure
+        let mut small = vec![0_u8; 16];
+        let handle = unsafe {
+            crate::tantivy_jpc_buf(
+                request.as_ptr(),
+                request.len(),
+                small.as_mut_ptr(),
+                small.len(),
+                &mut out_len,
+                &mut status,
+            )
+        };
+        assert!(handle > 0);
+        assert_eq!(status, -1);
+        assert_eq!(unsafe { free_data(handle) }, 0);
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn data_map_handles() {
+        crate::test_init();
+        let (first, stale) = {
+            let mut map = crate::DATA_MAP.lock().unwrap();
+            let first = crate::park_data(&mut map, vec![1, 2, 3]);
+            let stale = crate::park_data(&mut map, vec![0; 10]);
+            map.get_mut(&stale).unwrap().created -= std::time::Duration::from_secs(7200);
+            (first, stale)
+        };
+        assert!(stale > first);
+        let ctx = FakeContext::new();
+        let r = ctx.call_jsonrpc(
+            &json!({"jsonrpc": "2.0", "id": 1, "method": "system.data_map"}).to_string(),
+        );
+        assert!(r["result"]["handles"].as_u64().unwrap() >= 2);
+        assert!(r["result"]["bytes"].as_u64().unwrap() >= 13);
+        assert!(r["result"]["oldest_secs"].as_f64().unwrap() >= 7200.0);
+        let r = ctx.call_jsonrpc(
+            &json!({"jsonrpc": "2.0", "id": 2, "method": "system.data_map",
+                    "params": {"reclaim_after_secs": 3600}})
+            .to_string(),
+        );
+        assert!(r["result"]["reclaimed"].as_u64().unwrap() >= 1);
+        assert_eq!(unsafe { free_data(stale) }, -1);
+        assert_eq!(unsafe { free_data(first) }, 0);
+        let r = ctx.call_jsonrpc(
+            &json!({"jsonrpc": "2.0", "id": 3, "method": "system.data_map",
+                    "params": {"reclaim_after_secs": -1}})
+            .to_string(),
+        );
+        assert_eq!(r["error"]["code"], crate::jsonrpc::INVALID_PARAMS);
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn error_responses_freed() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let request = json!({
+            "id": ctx.id,
+            "jpc": "1.0",
+            "obj": "builder",
+            "method": "some_function",
+            "params": {},
+        })
+        .to_string();
+        let mut handles = vec![];
+        for _ in 0..20 {
+            let mut p: *const u8 = std::ptr::null();
+            let mut len = 0_usize;
+            let handle = unsafe { tantivy_jpc(request.as_ptr(), request.len(), &mut p, &mut len) };
+            assert!(handle < -1);
+            let sl = unsafe { ctx.ptr_to_vec(p, &len) };
+            let r: serde_json::Value = serde_json::from_slice(&sl).unwrap();
+            assert_eq!(r["kind"], "UnRecognizedCommand");
+            assert_eq!(unsafe { free_data(handle) }, 0);
+            handles.push(handle);
+        }
+        let map = crate::DATA_MAP.lock().unwrap();
+        assert!(handles.iter().all(|h| !map.contains_key(&-h)));
+        drop(map);
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    static FORWARDED: std::sync::Mutex<Vec<(i32, String, String)>> = std::sync::Mutex::new(vec![]);
+
+    unsafe extern "C" fn capture_log(
+        level: i32,
+        target: *const u8,
+        target_len: usize,
+        message: *const u8,
+        message_len: usize,
+    ) {
+        let target = std::slice::from_raw_parts(target, target_len);
+        let message = std::slice::from_raw_parts(message, message_len);
+        FORWARDED.lock().unwrap().push((
+            level,
+            String::from_utf8_lossy(target).to_string(),
+            String::from_utf8_lossy(message).to_string(),
+        ));
+    }
+
+    #[test]
+    fn log_callback() {
+        crate::test_init();
+        let marker = Uuid::new_v4().to_string();
+        unsafe {
+            assert_eq!(crate::set_log_callback(Some(capture_log), 9), -1);
+            assert_eq!(crate::set_log_callback(Some(capture_log), 3), 0);
+        }
+        info!("forwarded {marker}");
+        log::debug!("filtered {marker}");
+        unsafe {
+            assert_eq!(crate::set_log_level(4), 0);
+        }
+        log::debug!("raised {marker}");
+        unsafe {
+            assert_eq!(crate::set_log_callback(None, 5), 0);
+        }
+        info!("not forwarded {marker}");
+        let forwarded: Vec<(i32, String, String)> = FORWARDED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, _, m)| m.contains(&marker))
+            .cloned()
+            .collect();
+        assert_eq!(forwarded.len(), 2);
+        assert_eq!(forwarded[0].0, 3);
+        assert_eq!(forwarded[0].1, module_path!());
+        assert_eq!(forwarded[0].2, format!("forwarded {marker}"));
+        assert_eq!(forwarded[1].0, 4);
+        assert_eq!(forwarded[1].2, format!("raised {marker}"));
+    }
+
+    #[test]
+    fn call_metrics() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        assert_eq!(ctx.add_u64_field("count".to_string(), 2, true, true), 0);
+        ctx.call_jpc(
+            "builder".to_string(),
+            "some_function".to_string(),
+            json!({}),
+            true,
+        );
+        ctx.call_jpc(
+            "nothing".to_string(),
+            "some_function".to_string(),
+            json!({}),
+            true,
+        );
+        let mut p: *const u8 = std::ptr::null();
+        let mut len = 0_usize;
+        let handle = unsafe { crate::tantivy_jpc_metrics(&mut p, &mut len) };
+        assert!(handle > 0);
+        let text = std::str::from_utf8(unsafe { std::slice::from_raw_parts(p, len) })
+            .unwrap()
+            .to_string();
+        assert_eq!(unsafe { free_data(handle) }, 0);
+        assert!(text.contains("# TYPE tantivy_jpc_calls_total counter"));
+        assert!(text.contains("tantivy_jpc_calls_total{obj=\"builder\",method=\"add_u64_field\"}"));
+        assert!(text.contains(
+            "tantivy_jpc_errors_total{obj=\"unknown\",method=\"unknown\",kind=\"UnRecognizedCommand\"}"
+        ));
+        assert!(text.contains(
+            "tantivy_jpc_call_duration_seconds_bucket{obj=\"builder\",method=\"add_u64_field\",le=\"+Inf\"}"
+        ));
+        assert!(text.contains(
+            "tantivy_jpc_response_bytes_count{obj=\"builder\",method=\"add_u64_field\"}"
+        ));
+        assert!(!text.contains("some_function"));
+        let response = json!({"jsonrpc": "2.0", "id": 1, "result": {"field": [0, "a\"b"]}});
+        assert_eq!(
+            crate::metrics::json_len(&response),
+            response.to_string().len()
+        );
+        let r = ctx.call_jsonrpc(
+            &json!({"jsonrpc": "2.0", "id": 1, "method": "system.metrics"}).to_string(),
+        );
+        assert!(r["result"]["metrics"]
+            .as_str()
+            .unwrap()
+            .contains("tantivy_jpc_calls_total"));
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn builder_from_json() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        assert_eq!(
+            ctx.add_text_field(
+                "title".to_string(),
+                2,
+                true,
+                true,
+                "en_stem_with_stop_words".to_string(),
+                false
+            ),
+            0
+        );
+        assert_eq!(ctx.add_u64_field("count".to_string(), 2, true, true), 1);
+        let built = ctx.call_json("builder", "build", json!({}));
+
+        let loaded = FakeContext::new();
+        let r = loaded.call_json("builder", "from_json", built.clone());
+        assert_eq!(r["schema"], built["schema"]);
+        let n = loaded.call_json("schema", "num_fields", json!({}));
+        assert_eq!(n, 2);
+
+        let mut fields = built["schema"].as_array().unwrap().clone();
+        let r = loaded.call_json("builder", "from_json", json!(fields.clone()));
+        assert_eq!(r["schema"], built["schema"]);
+        fields.push(fields[0].clone());
+        let r = loaded.call_json("builder", "from_json", json!({ "schema": fields }));
+        assert_eq!(
+            r["error"],
+            "handle builder error=BadParams : `field title defined more than once`"
+        );
+        let mut unknown = built["schema"][0].clone();
+        unknown["options"]["indexing"]["tokenizer"] = json!("no_such_tokenizer");
+        let r = loaded.call_json("builder", "from_json", json!({ "schema": [unknown] }));
+        assert_eq!(r["kind"], "BadParams");
+        let r = loaded.call_json(
+            "builder",
+            "from_json",
+            json!({ "schema": [{"name": "bad"}] }),
+        );
+        assert_eq!(r["kind"], "BadParams");
+        let _ = crate::do_term(&ctx.id);
+        let _ = crate::do_term(&loaded.id);
+    }
+
+    #[test]
+    fn builder_and_document_extra_types() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        let mut fields = Vec::new();
+        for method in [
+            "add_bool_field",
+            "add_bytes_field",
+            "add_ip_addr_field",
+            "add_facet_field",
+        ] {
+            let r = ctx.call_json(
+                "builder",
+                method,
+                json!({"name": method, "type": 0, "stored": true, "indexed": true}),
+            );
+            fields.push(r["field"].as_u64().unwrap());
+        }
+        assert_eq!(fields, vec![0, 1, 2, 3]);
+        let r = ctx.call_json(
+            "builder",
+            "add_facet_field",
+            json!({"name": "fast_facet", "type": 0, "fast": true}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+        let r = ctx.call_json("builder", "build", json!({}));
+        assert_eq!(r["schema"][2]["type"], "ip_addr");
+        assert_eq!(r["schema"][3]["type"], "facet");
+
+        assert_eq!(
+            ctx.call_json("document", "create", json!({}))["document_count"],
+            1
+        );
+        for (method, field, value) in [
+            ("add_bool", 0, json!(true)),
+            ("add_bytes", 1, json!("aGVsbG8=")),
+            ("add_bytes", 1, json!([104, 105])),
+            ("add_ip_addr", 2, json!("192.168.0.1")),
+            ("add_ip_addr", 2, json!("::1")),
+            ("add_facet", 3, json!("/books/fiction")),
+        ] {
+            let r = ctx.call_json(
+                "document",
+                method,
+                json!({"doc_id": 1, "field": field, "value": value}),
+            );
+            assert!(r.get("error").is_none(), "{method} {value} {r}");
+        }
+        for (method, field, value) in [
+            ("add_bool", 0, json!("yes")),
+            ("add_bytes", 1, json!("not base64!")),
+            ("add_bytes", 1, json!([256])),
+            ("add_ip_addr", 2, json!("300.1.1.1")),
+            ("add_facet", 3, json!("books")),
+        ] {
+            let r = ctx.call_json(
+                "document",
+                method,
+                json!({"doc_id": 1, "field": field, "value": value}),
+            );
+            assert_eq!(r["kind"], "BadParams", "{method} {value}");
+        }
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn builder_field_options() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        ctx.call_json(
+            "builder",
+            "add_text_field",
+            json!({"name": "title", "type": 2, "stored": true, "record": "freq", "fieldnorms": false}),
+        );
+        ctx.call_json(
+            "builder",
+            "add_i64_field",
+            json!({"name": "count", "type": 0, "indexed": true, "fieldnorms": true, "coerce": true}),
+        );
+        ctx.call_json(
+            "builder",
+            "add_date_field",
+            json!({"name": "when", "type": 0, "fast": true, "precision": "milliseconds"}),
+        );
+        for (method, params) in [
+            (
+                "add_text_field",
+                json!({"name": "a", "type": 2, "precision": "seconds"}),
+            ),
+            (
+                "add_text_field",
+                json!({"name": "b", "type": 2, "record": "all"}),
+            ),
+            (
+                "add_text_field",
+                json!({"name": "c", "type": 2, "fast": true, "fast_tokenizer": "raw"}),
+            ),
+            (
+                "add_u64_field",
+                json!({"name": "d", "type": 0, "coerce": "yes"}),
+            ),
+            (
+                "add_date_field",
+                json!({"name": "e", "type": 0, "precision": "days"}),
+            ),
+            (
+                "add_bytes_field",
+                json!({"name": "f", "type": 0, "fieldnorms": true}),
+            ),
+        ] {
+            let r = ctx.call_json("builder", method, params.clone());
+            assert_eq!(r["kind"], "BadParams", "{method} {params}");
+        }
+        let r = ctx.call_json(
+            "builder",
+            "add_f64_field",
+            json!({"name": "g", "type": 0, "stord": true}),
+        );
+        assert!(r["error"]
+            .as_str()
+            .unwrap()
+            .contains("unknown option stord"));
+        let r = ctx.call_json(
+            "builder",
+            "add_text_field",
+            json!({"name": "h", "type": 2, "fast": true, "fast_tokenizer": "raw"}),
+        );
+        assert!(r["error"]
+            .as_str()
+            .unwrap()
+            .contains("fast_tokenizer is not supported"));
+
+        let r = ctx.call_json("builder", "build", json!({}));
+        let schema = r["schema"].as_array().unwrap();
+        assert_eq!(schema.len(), 3);
+        assert_eq!(schema[0]["options"]["indexing"]["record"], "freq");
+        assert_eq!(schema[0]["options"]["indexing"]["fieldnorms"], false);
+        assert_eq!(schema[1]["options"]["fieldnorms"], true);
+        assert_eq!(schema[1]["options"]["coerce"], true);
+        assert_eq!(schema[2]["options"]["precision"], "milliseconds");
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn json_field_indexing() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        assert_eq!(
+            ctx.add_text_field(
+                "title".to_string(),
+                2,
+                true,
+                true,
+                "en_stem_with_stop_words".to_string(),
+                false
+            ),
+            0
+        );
+        let r = ctx.call_json(
+            "builder",
+            "add_json_field",
+            json!({"name": "attrs", "type": 2, "stored": true, "indexed": true,
+                   "tokenizer": "en_stem_with_stop_words", "record": "freq", "expand_dots": true}),
+        );
+        assert_eq!(r["field"], 1);
+        let mut td = ctx.build(true).unwrap();
+        let doc1 = td.create().unwrap();
+        let doc2 = td.create().unwrap();
+        td.add_text(0, "Fire truck".to_string(), doc1 as u32);
+        td.add_json(
+            1,
+            json!({"color": "Red", "size": {"label": "large"}}),
+            doc1 as u32,
+        );
+        td.add_text(0, "Sky".to_string(), doc2 as u32);
+        td.add_json(
+            1,
+            json!({"color": "blue", "size.label": "huge"}),
+            doc2 as u32,
+        );
+        let mut ti = td.create_index().unwrap();
+        ti.add_document(doc1 as i32).unwrap();
+        ti.add_document(doc2 as i32).unwrap();
+        ti.commit().unwrap();
+        let mut rb = ti.reader_builder().unwrap();
+        let mut qp = rb.searcher().unwrap();
+        qp.for_index(vec!["title".to_string()]).unwrap();
+        for (query, title) in [
+            ("attrs.color:reds", "Fire truck"),
+            ("attrs.size.label:large", "Fire truck"),
+            ("attrs.size.label:huge", "Sky"),
+        ] {
+            let mut searcher = qp.parse_query(query.to_string()).unwrap();
+            let sres = searcher.search(10, true, vec![]).unwrap();
+            let res: Vec<ResultElement> = serde_json::from_str(&sres).unwrap();
+            assert_eq!(res.len(), 1, "{query}");
+            assert_eq!(
+                res[0].doc.0.get("title").unwrap()[0].as_text().unwrap(),
+                title,
+                "{query}"
+            );
+        }
+        let _ = crate::do_term(&ti.ctx.id);
+    }
+
+    #[test]
+    fn json_field_indexed_by_default() {
+        crate::test_init();
+        let ctx = FakeContext::new();
+        // the params the go client sends, without indexed
+        let r = ctx.call_json(
+            "builder",
+            "add_json_field",
+            json!({"name": "attrs", "type": 2, "stored": true, "id": ctx.id, "fast": false,
+                   "basic": false, "tokenizer": "en_stem_with_stop_words"}),
+        );
+        assert_eq!(r["field"], 0);
+        let r = ctx.call_json(
+            "builder",
+            "add_json_field",
+            json!({"name": "raw", "type": 2, "stored": true, "indexed": false}),
+        );
+        assert_eq!(r["field"], 1);
+        let r = ctx.call_json("builder", "build", json!({}));
+        let schema = r["schema"].as_array().unwrap();
+        assert_eq!(
+            schema[0]["options"]["indexing"]["tokenizer"],
+            "en_stem_with_stop_words"
+        );
+        assert!(schema[1]["options"]["indexing"].is_null());
+        let _ = crate::do_term(&ctx.id);
+    }
+
+    #[test]
+    fn schema_documents() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_text_field(
+            "title".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        ctx.add_u64_field("count".to_string(), 2, true, true);
+        ctx.add_json_field(
+            "attrs".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        let mut td = ctx.build(true).unwrap();
+        let c = td.ctx.clone();
+        let r = c.call_json(
+            "schema",
+            "json_object_to_doc",
+            json!({"document": {"title": "Red", "count": 3, "attrs": {"color": "red"}}}),
+        );
+        assert_eq!(r["document_count"], 1);
+        let r = c.call_json(
+            "schema",
+            "parse_document",
+            json!({"document": r#"{"title": "Blue", "count": 4}"#}),
+        );
+        assert_eq!(r["document_count"], 2);
+        let r = c.call_json(
+            "schema",
+            "convert_named_doc",
+            json!({"document": {"title": ["Green"], "count": [5]}}),
+        );
+        assert_eq!(r["document_count"], 3);
+        let r = c.call_json(
+            "schema",
+            "convert_named_doc",
+            json!({"document": {"title": "Green"}}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+        let r = c.call_json("schema", "parse_document", json!({"document": "{not json"}));
+        assert_eq!(r["kind"], "BadParams");
+        for (method, document) in [
+            ("json_object_to_doc", json!({"title": "Red", "pages": 3})),
+            ("parse_document", json!(r#"{"title": "Red", "pages": 3}"#)),
+            ("convert_named_doc", json!({"title": ["Red"], "pages": [3]})),
+        ] {
+            let r = c.call_json("schema", method, json!({ "document": document }));
+            assert!(
+                r["error"]
+                    .as_str()
+                    .unwrap()
+                    .contains("field pages is not in the schema"),
+                "{method} {r}"
+            );
+        }
+
+        let r = c.call_json("schema", "to_json", json!({"doc_id": 1}));
+        assert_eq!(r["title"], json!(["Red"]));
+        assert_eq!(r["attrs"], json!([{"color": "red"}]));
+        let r = c.call_json("schema", "to_named_doc", json!({"doc_id": 3}));
+        assert_eq!(r, json!({"title": ["Green"], "count": [5]}));
+        let r = c.call_json("schema", "to_json", json!({"doc_id": 4}));
+        assert_eq!(r["kind"], "BadParams");
+        let r = c.call_json("schema", "to_json", json!({"doc_id": 0, "segment_ord": 0}));
+        assert_eq!(r["kind"], "BadInitialization");
+
+        let r = c.call_json("schema", "find_field", json!({"field": "attrs.color"}));
+        assert_eq!(r, json!({"field": 2, "name": "attrs", "path": "color"}));
+        let r = c.call_json("schema", "find_field", json!({"field": "title"}));
+        assert_eq!(r, json!({"field": 0, "name": "title", "path": ""}));
+        let r = c.call_json("schema", "find_field", json!({"field": "missing.path"}));
+        assert_eq!(r["kind"], "NotExist");
+        let r = c.call_json("schema", "get_field", json!({}));
+        assert_eq!(r["kind"], "BadParams");
+        let r = c.call_json("schema", "get_field_entry", json!({"field": []}));
+        assert_eq!(r["kind"], "BadParams");
+
+        let mut ti = td.create_index().unwrap();
+        ti.add_document(1).unwrap();
+        ti.commit().unwrap();
+        let mut rb = ti.reader_builder().unwrap();
+        rb.searcher().unwrap();
+        let r = c.call_json("schema", "to_json", json!({"doc_id": 0, "segment_ord": 0}));
+        assert_eq!(r["title"], json!(["Red"]));
+        let r = c.call_json("schema", "to_json", json!({"doc_id": 0, "segment_ord": 5}));
+        assert_eq!(r["kind"], "BadParams");
+        let r = c.call_json(
+            "schema",
+            "to_named_doc",
+            json!({"doc_id": 99, "segment_ord": 0}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+        let _ = crate::do_term(&c.id);
+    }
+
+    #[test]
+    fn index_open_modes() {
+        crate::test_init();
+        let dir = TempDir::new("TantivyOpenModes").unwrap();
+        let empty = TempDir::new("TantivyOpenModesEmpty").unwrap();
+        let path = dir.path().to_str().unwrap();
+        let open = |fields: &[(&str, i32)], directory: &str, mode: &str| -> serde_json::Value {
+            let mut ctx = FakeContext::new();
+            for (name, t) in fields {
+                ctx.add_text_field(
+                    name.to_string(),
+                    *t,
+                    true,
+                    true,
+                    "en_stem_with_stop_words".to_string(),
+                    false,
+                );
+            }
+            if !fields.is_empty() {
+                ctx.call_jpc("builder".to_string(), "build".to_string(), json!({}), false);
+            }
+            let r = ctx.call_jpc(
+                "index".to_string(),
+                "create".to_string(),
+                json!({"directory": directory, "mode": mode}),
+                true,
+            );
+            let r = serde_json::from_slice(&r).unwrap_or(json!({}));
+            let _ = crate::do_term(&ctx.id);
+            r
+        };
+        assert!(open(&[("title", 2)], path, "create").get("error").is_none());
+        let r = open(&[("title", 2)], path, "create");
+        assert_eq!(r["kind"], "BadParams");
+        assert!(open(&[("title", 2)], path, "create_or_open")
+            .get("error")
+            .is_none());
+        assert!(open(&[], path, "open").get("error").is_none());
+
+        let r = open(&[("title", 2), ("body", 2)], path, "open");
+        assert_eq!(r["kind"], "SchemaMismatch");
+        assert_eq!(r["data"]["diff"]["added"][0]["entry"]["name"], "body");
+        assert_eq!(r["data"]["diff"]["added"][0]["field"], 1);
+        let r = open(&[("body", 2)], path, "create_or_open");
+        assert_eq!(r["kind"], "SchemaMismatch");
+        assert_eq!(r["data"]["diff"]["added"][0]["entry"]["name"], "body");
+        assert_eq!(r["data"]["diff"]["removed"][0]["entry"]["name"], "title");
+        let r = open(&[("title", 1)], path, "open");
+        assert_eq!(r["kind"], "SchemaMismatch");
+        assert_eq!(r["data"]["diff"]["changed"][0]["name"], "title");
+        assert_eq!(r["data"]["diff"]["added"], json!([]));
+
+        let r = open(&[("title", 2)], empty.path().to_str().unwrap(), "open");
+        assert_eq!(r["kind"], "NotExist");
+        let missing = empty.path().join("missing");
+        let r = open(&[("title", 2)], missing.to_str().unwrap(), "open");
+        assert_eq!(r["kind"], "NotExist");
+        let r = open(&[("title", 2)], "", "open");
+        assert_eq!(r["kind"], "BadParams");
+        let r = open(&[("title", 2)], path, "replace");
+        assert_eq!(r["kind"], "BadParams");
+    }
+
+    #[test]
+    fn document_fields_by_name() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_text_field(
+            "title".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        ctx.add_i64_field("order".to_string(), 2, true, true);
+        let td = ctx.build(true).unwrap();
+        let c = td.ctx.clone();
+        assert_eq!(
+            c.call_json("document", "create", json!({}))["document_count"],
+            1
+        );
+        for (method, field, value) in [
+            ("add_text", json!("title"), json!("Cannery Row")),
+            ("add_text", json!(0), json!("Tortilla Flat")),
+            ("add_int", json!("order"), json!(7)),
+        ] {
+            let r = c.call_json(
+                "document",
+                method,
+                json!({"doc_id": 1, "field": field, "value": value}),
+            );
+            assert!(r.get("error").is_none(), "{method} {field} {r}");
+        }
+        let r = c.call_json(
+            "document",
+            "add_int",
+            json!({"doc_id": 1, "field": "title", "value": 3}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+        assert_eq!(
+            r["error"],
+            "handle document error=BadParams : `field title expects text values, got i64`"
+        );
+        assert_eq!(r["data"]["field"], "title");
+        for field in [json!("missing"), json!(2), json!(-1), json!(true)] {
+            let r = c.call_json(
+                "document",
+                "add_text",
+                json!({"doc_id": 1, "field": field, "value": "x"}),
+            );
+            assert_eq!(r["kind"], "BadParams", "{field}");
+        }
+        let r = c.call_json(
+            "document",
+            "add_text",
+            json!({"doc_id": 0, "field": "title", "value": "x"}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+
+        let r = c.call_json("schema", "to_named_doc", json!({"doc_id": 1}));
+        assert_eq!(
+            r,
+            json!({"title": ["Cannery Row", "Tortilla Flat"], "order": [7]})
+        );
+        let _ = crate::do_term(&c.id);
+    }
+
+    #[test]
+    fn add_json_doc() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_text_field(
+            "title".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        ctx.add_text_field(
+            "tags".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        ctx.add_u64_field("count".to_string(), 2, true, true);
+        let mut td = ctx.build(true).unwrap();
+        let c = td.ctx.clone();
+        let r = c.call_json(
+            "document",
+            "add_json_doc",
+            json!({"document": {"title": "East of Eden"}, "add": true}),
+        );
+        assert_eq!(r["kind"], "BadInitialization");
+        let r = c.call_json(
+            "document",
+            "add_json_doc",
+            json!({"document": {"title": "East of Eden", "tags": ["novel", "salinas"], "count": 2}}),
+        );
+        assert_eq!(r["doc_id"], 1);
+        let r = c.call_json(
+            "document",
+            "add_json_doc",
+            json!({"document": {"title": "Cup of Gold", "pages": 1}}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+        assert!(r["error"]
+            .as_str()
+            .unwrap()
+            .contains("field pages is not in the schema"));
+        let r = c.call_json(
+            "document",
+            "add_json_doc",
+            json!({"document": {"title": "Cup of Gold", "count": "many"}}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+        let r = c.call_json(
+            "document",
+            "add_json_doc",
+            json!({"document": ["Cup of Gold"]}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+
+        let mut ti = td.create_index().unwrap();
+        ti.add_document(1).unwrap();
+        let r = c.call_json(
+            "document",
+            "add_json_doc",
+            json!({"document": {"title": "The Pearl", "tags": ["novella"]}, "add": true}),
+        );
+        assert!(r["opstamp"].is_u64(), "{r}");
+        ti.commit().unwrap();
+        let mut rb = ti.reader_builder().unwrap();
+        let mut qp = rb.searcher().unwrap();
+        qp.for_index(vec!["tags".to_string()]).unwrap();
+        for (query, title) in [("salinas", "East of Eden"), ("novella", "The Pearl")] {
+            let mut searcher = qp.parse_query(query.to_string()).unwrap();
+            let sres = searcher.search(10, true, vec![]).unwrap();
+            let res: Vec<ResultElement> = serde_json::from_str(&sres).unwrap();
+            assert_eq!(res.len(), 1, "{query}");
+            assert_eq!(
+                res[0].doc.0.get("title").unwrap()[0].as_text().unwrap(),
+                title
+            );
+        }
+        let _ = crate::do_term(&c.id);
+    }
+
+    #[test]
+    fn ingest_ndjson() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_text_field(
+            "title".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        ctx.add_u64_field("count".to_string(), 2, true, true);
+        let mut td = ctx.build(true).unwrap();
+        let mut ti = td.create_index().unwrap();
+        let c = ti.ctx.clone();
+        let data = [
+            r#"{"title": "The Red Pony", "count": 1}"#,
+            "",
+            r#"{"title": "#,
+            r#"["Sweet Thursday"]"#,
+            r#"{"title": "Sweet Thursday", "pages": 273}"#,
+            r#"{"title": "Sweet Thursday", "count": "one"}"#,
+            r#"{"title": ["The Winter of Our Discontent", "Discontent"]}"#,
+        ]
+        .join("\n");
+        let r = c.call_json(
+            "indexwriter",
+            "ingest_ndjson",
+            json!({"data": data, "commit_every": 1}),
+        );
+        assert_eq!(r["lines"], 6);
+        assert_eq!(r["added"], 2);
+        assert_eq!(r["failed"], 4);
+        assert_eq!(r["commits"], 2);
+        let lines: Vec<u64> = r["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["line"].as_u64().unwrap())
+            .collect();
+        assert_eq!(lines, vec![3, 4, 5, 6]);
+        assert!(r["errors"][2]["error"]
+            .as_str()
+            .unwrap()
+            .contains("field pages is not in the schema"));
+
+        let r = c.call_json(
+            "indexwriter",
+            "ingest_ndjson",
+            json!({"data": data, "max_errors": 1}),
+        );
+        assert_eq!(r["failed"], 4);
+        assert_eq!(r["errors"].as_array().unwrap().len(), 1);
+        assert_eq!(r["commits"], 0);
+
+        let dir = TempDir::new("TantivyIngest").unwrap();
+        let path = dir.path().join("books.ndjson");
+        std::fs::write(&path, "{\"title\": \"Tortilla Flat\"}\n").unwrap();
+        let r = c.call_json("indexwriter", "ingest_ndjson", json!({"path": path}));
+        assert_eq!(r["added"], 1);
+        let r = c.call_json(
+            "indexwriter",
+            "ingest_ndjson",
+            json!({"path": dir.path().join("missing.ndjson")}),
+        );
+        assert_eq!(r["kind"], "IO");
+        let r = c.call_json(
+            "indexwriter",
+            "ingest_ndjson",
+            json!({"data": data, "path": path}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+        ti.commit().unwrap();
+
+        let mut rb = ti.reader_builder().unwrap();
+        let mut qp = rb.searcher().unwrap();
+        qp.for_index(vec!["title".to_string()]).unwrap();
+        for (query, hits) in [
+            ("pony", 2),
+            ("discontent", 2),
+            ("tortilla", 1),
+            ("thursday", 0),
+        ] {
+            let mut searcher = qp.parse_query(query.to_string()).unwrap();
+            let sres = searcher.search(10, true, vec![]).unwrap();
+            let res: Vec<ResultElement> = serde_json::from_str(&sres).unwrap_or_default();
+            assert_eq!(res.len(), hits, "{query}");
+        }
+        let _ = crate::do_term(&c.id);
+    }
+
+    #[test]
+    fn document_value_setters() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_text_field(
+            "body".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        ctx.add_f64_field("price".to_string(), 2, true, true);
+        ctx.add_date_field("when".to_string(), 2, true, true);
+        let r = ctx.call_json(
+            "builder",
+            "add_ip_addr_field",
+            json!({"name": "addr", "type": 0, "stored": true}),
+        );
+        assert_eq!(r["field"], 3);
+        let td = ctx.build(true).unwrap();
+        let c = td.ctx.clone();
+        c.call_json("document", "create", json!({}));
+        let tokens = json!({
+            "text": "hello world",
+            "tokens": [
+                {"offset_from": 0, "offset_to": 5, "position": 0, "text": "hello", "position_length": 1},
+                {"offset_from": 6, "offset_to": 11, "position": 1, "text": "world", "position_length": 1}
+            ]
+        });
+        for (method, field, value, precision) in [
+            ("add_f64", "price", json!(9.5), None),
+            ("add_date", "when", json!("2023-11-14T22:13:20Z"), None),
+            ("add_date", "when", json!(1_700_000_000), None),
+            (
+                "add_date",
+                "when",
+                json!(1_700_000_000_000_i64),
+                Some("milliseconds"),
+            ),
+            (
+                "add_date",
+                "when",
+                json!(1_700_000_000_000_000_i64),
+                Some("microseconds"),
+            ),
+            ("add_ip", "addr", json!("10.0.0.1"), None),
+            ("add_pretokenized", "body", tokens, None),
+        ] {
+            let mut params = json!({"doc_id": 1, "field": field, "value": value});
+            if let Some(p) = precision {
+                params["precision"] = json!(p);
+            }
+            let r = c.call_json("document", method, params);
+            assert!(r.get("error").is_none(), "{method} {value} {r}");
+        }
+        for (method, field, value, precision) in [
+            ("add_f64", "price", json!("cheap"), None),
+            ("add_date", "when", json!("yesterday"), None),
+            ("add_date", "when", json!(1), Some("fortnights")),
+            ("add_pretokenized", "body", json!("hello world"), None),
+            ("add_f64", "when", json!(1.5), None),
+        ] {
+            let mut params = json!({"doc_id": 1, "field": field, "value": value});
+            if let Some(p) = precision {
+                params["precision"] = json!(p);
+            }
+            let r = c.call_json("document", method, params);
+            assert_eq!(r["kind"], "BadParams", "{method} {value}");
+        }
+        let r = c.call_json("schema", "to_named_doc", json!({"doc_id": 1}));
+        assert_eq!(r["price"], json!([9.5]));
+        let when = r["when"].as_array().unwrap();
+        assert_eq!(when.len(), 4);
+        assert!(when.iter().all(|w| *w == when[0]), "{when:?}");
+        assert_eq!(r["body"].as_array().unwrap().len(), 1);
+        assert_eq!(r["addr"].as_array().unwrap().len(), 1);
+        let _ = crate::do_term(&c.id);
+    }
+
+    #[test]
+    fn strict_and_lenient_setters() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_text_field(
+            "title".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        ctx.add_i64_field("order".to_string(), 2, true, true);
+        ctx.add_u64_field("count".to_string(), 2, true, true);
+        ctx.add_f64_field("price".to_string(), 2, true, true);
+        ctx.add_json_field(
+            "attrs".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        let td = ctx.build(true).unwrap();
+        let c = td.ctx.clone();
+        c.call_json("document", "create", json!({}));
+        for (method, field, value) in [
+            ("add_text", "title", json!(42)),
+            ("add_int", "order", json!("7")),
+            ("add_int", "order", json!(7.5)),
+            ("add_uint", "count", json!(-1)),
+            ("add_f64", "price", json!("9.5")),
+            ("add_json", "attrs", json!("not an object")),
+            ("add_json", "attrs", json!([1, 2])),
+        ] {
+            let r = c.call_json(
+                "document",
+                method,
+                json!({"doc_id": 1, "field": field, "value": value}),
+            );
+            assert_eq!(r["kind"], "BadParams", "{method} {value}");
+            assert!(
+                r["error"].as_str().unwrap().contains(&value.to_string()),
+                "{r}"
+            );
+        }
+        for (method, field, value) in [
+            ("add_text", "title", json!(42)),
+            ("add_int", "order", json!(" 7 ")),
+            ("add_int", "order", json!(8.0)),
+            ("add_uint", "count", json!("3")),
+            ("add_f64", "price", json!("9.5")),
+            ("add_json", "attrs", json!(r#"{"color": "red"}"#)),
+        ] {
+            let r = c.call_json(
+                "document",
+                method,
+                json!({"doc_id": 1, "field": field, "value": value, "lenient": true}),
+            );
+            assert!(r.get("error").is_none(), "{method} {value} {r}");
+        }
+        for (method, field, value) in [
+            ("add_int", "order", json!(7.5)),
+            ("add_uint", "count", json!(-1)),
+            ("add_json", "attrs", json!("[1, 2]")),
+            ("add_f64", "price", json!("NaN")),
+        ] {
+            let r = c.call_json(
+                "document",
+                method,
+                json!({"doc_id": 1, "field": field, "value": value, "lenient": true}),
+            );
+            assert_eq!(r["kind"], "BadParams", "{method} {value}");
+        }
+        for lenient in [json!("true"), json!(1), json!(null)] {
+            let r = c.call_json(
+                "document",
+                "add_int",
+                json!({"doc_id": 1, "field": "order", "value": 9, "lenient": lenient}),
+            );
+            assert_eq!(r["kind"], "BadParams", "{lenient}");
+            assert!(r["error"]
+                .as_str()
+                .unwrap()
+                .contains("lenient must be true or false"));
+        }
+        let r = c.call_json("schema", "to_named_doc", json!({"doc_id": 1}));
+        assert_eq!(
+            r,
+            json!({
+                "title": ["42"],
+                "order": [7, 8],
+                "count": [3],
+                "price": [9.5],
+                "attrs": [{"color": "red"}],
+            })
+        );
+        let _ = crate::do_term(&c.id);
+    }
+
+    #[test]
+    fn pending_documents() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_text_field(
+            "title".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        ctx.add_i64_field("order".to_string(), 2, true, true);
+        let mut td = ctx.build(true).unwrap();
+        let c = td.ctx.clone();
+        for (doc_id, title) in [
+            (1, "Cannery Row"),
+            (2, "Sweet Thursday"),
+            (3, "The Red Pony"),
+        ] {
+            let r = c.call_json("document", "create", json!({}));
+            assert_eq!(r, json!({"doc_id": doc_id, "document_count": doc_id}));
+            let r = c.call_json(
+                "document",
+                "add_text",
+                json!({"doc_id": doc_id, "field": "title", "value": title}),
+            );
+            assert!(r.get("error").is_none(), "{r}");
+        }
+        let r = c.call_json(
+            "document",
+            "add_int",
+            json!({"doc_id": 2, "field": "order", "value": 7}),
+        );
+        assert!(r.get("error").is_none(), "{r}");
+
+        let mut ti = td.create_index().unwrap();
+        ti.add_document(1).unwrap();
+        // handles are never reused, so a new document cannot collide with a pending one
+        assert_eq!(c.call_json("document", "create", json!({}))["doc_id"], 4);
+        let r = c.call_json("indexwriter", "add_document", json!({"id": 1}));
+        assert_eq!(r["kind"], "BadParams");
+        assert!(r["error"]
+            .as_str()
+            .unwrap()
+            .contains("document 1 is no longer pending, it was already added or discarded"));
+        let r = c.call_json("indexwriter", "add_document", json!({"id": 9}));
+        assert!(r["error"]
+            .as_str()
+            .unwrap()
+            .contains("document 9 does not exist"));
+        let r = c.call_json("indexwriter", "add_document", json!({}));
+        assert_eq!(r["kind"], "BadParams");
+
+        let r = c.call_json("document", "list", json!({}));
+        assert_eq!(r, json!({"doc_ids": [2, 3, 4], "next_doc_id": 5}));
+        let r = c.call_json("document", "inspect", json!({"doc_id": 2}));
+        assert_eq!(
+            r,
+            json!({"doc_id": 2, "document": {"title": ["Sweet Thursday"], "order": [7]}})
+        );
+        let r = c.call_json(
+            "document",
+            "clear_field",
+            json!({"doc_id": 2, "field": "title"}),
+        );
+        assert_eq!(r, json!({"doc_id": 2, "removed": 1}));
+        let r = c.call_json("document", "inspect", json!({"doc_id": 2}));
+        assert_eq!(r["document"], json!({"order": [7]}));
+        let r = c.call_json(
+            "document",
+            "clear_field",
+            json!({"doc_id": 1, "field": "title"}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+
+        assert_eq!(
+            c.call_json("document", "discard", json!({"doc_id": 3})),
+            json!({"discarded": 3})
+        );
+        let r = c.call_json("document", "discard", json!({"doc_id": 3}));
+        assert!(r["error"].as_str().unwrap().contains("no longer pending"));
+        let r = c.call_json(
+            "document",
+            "add_text",
+            json!({"doc_id": 3, "field": "title", "value": "x"}),
+        );
+        assert_eq!(r["kind"], "BadParams");
+        let r = c.call_json("document", "list", json!({}));
+        assert_eq!(r["doc_ids"], json!([2, 4]));
+
+        ti.add_document(2).unwrap();
+        ti.commit().unwrap();
+        let _ = crate::do_term(&c.id);
+    }
+
+    #[test]
+    fn close_drops_pending_documents() {
+        crate::test_init();
+        let mut ctx = FakeContext::new();
+        ctx.add_text_field(
+            "title".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        let td = ctx.build(true).unwrap();
+        for doc_id in 1..=2 {
+            let r = td.ctx.call_json("document", "create", json!({}));
+            assert_eq!(r["doc_id"], doc_id);
+        }
+        let closed = ctx.call_json("session", "close", json!({}));
+        assert_eq!(closed["closed"], ctx.id);
+
+        // the session created again under the same id has no pending documents and starts over
+        ctx.add_text_field(
+            "title".to_string(),
+            2,
+            true,
+            true,
+            "en_stem_with_stop_words".to_string(),
+            false,
+        );
+        let td = ctx.build(true).unwrap();
+        let r = td.ctx.call_json("document", "list", json!({}));
+        assert_eq!(r, json!({"doc_ids": [], "next_doc_id": 1}));
+        let r = td
+            .ctx
+            .call_json("document", "inspect", json!({"doc_id": 2}));
+        assert_eq!(r["kind"], "BadParams");
+        let r = td.ctx.call_json("document", "create", json!({}));
+        assert_eq!(r["doc_id"], 1);
+        let _ = crate::do_term(&ctx.id);
+    }
 }